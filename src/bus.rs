@@ -0,0 +1,370 @@
+//! Sharing one I2C or SPI bus between several drivers
+//!
+//! A `BusManager` owns the bus and hands out proxies that implement the
+//! `embedded-hal` traits. Each transaction of a proxy locks the bus, so
+//! drivers running in different threads don't interleave their transfers.
+//! SPI proxies also carry their own chip select pin and bus settings, which
+//! are applied at the start of every transaction.
+//!
+//! ``` ignore
+//! let i2c = BusManager::new(I2c).unwrap();
+//! let mut sensor = Sensor::new(i2c.acquire());
+//! let mut eeprom = Eeprom::new(i2c.acquire());
+//!
+//! let spi = BusManager::new(Spi).unwrap();
+//! let mut display = Display::new(spi.acquire_spi(D5, display_settings));
+//! let mut flash = Flash::new(spi.acquire_spi(D6, flash_settings));
+//! ```
+//!
+//! With system threading enabled the bus is guarded by a recursive RTOS
+//! mutex: other threads wait for the transaction to end, and the thread that
+//! holds the bus can lock it again, e.g. from the closure of
+//! `BusManager::lock`. SPI transactions can't nest though, as that would
+//! select a second device while the first one is still selected; the inner
+//! transaction fails with `Error::Busy`.
+//!
+//! Without threading, the application thread is the only one that can use
+//! the bus and the lock is just a flag. A critical section would also keep
+//! out the interrupt handlers, but masking the interrupts for a whole
+//! transfer stops the system tick that the I2C timeouts count on, so a stuck
+//! bus would hang the device, and it holds off the system's own interrupts
+//! for as long as the transfer lasts. Finding the bus taken means that the
+//! transaction was started from within another one, or from an interrupt
+//! handler that preempted one; waiting would never end, so these
+//! transactions fail instead.
+
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use hal::blocking::{i2c, spi};
+use hal::digital::v2::OutputPin;
+use ll;
+use spi::Settings;
+use {I2c, Spi, Spi1};
+
+/// Lock that's either a recursive RTOS mutex or a flag
+struct Lock {
+    mutex: ll::os_mutex_recursive_t,
+    busy: AtomicBool,
+}
+
+impl Lock {
+    /// Returns an error if the mutex can't be created
+    fn new() -> Result<Self, ()> {
+        let mut mutex = ptr::null_mut();
+
+        unsafe {
+            if ll::system_thread_get_state(ptr::null_mut()) ==
+                ll::FEATURE_ENABLED &&
+                ll::os_mutex_recursive_create(&mut mutex) != 0
+            {
+                return Err(());
+            }
+        }
+
+        Ok(Lock {
+            mutex,
+            busy: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns an error if the flag is already set
+    fn lock<R, F>(&self, f: F) -> Result<R, ()>
+    where
+        F: FnOnce() -> R,
+    {
+        if self.mutex.is_null() {
+            if self.busy.swap(true, Ordering::Acquire) {
+                return Err(());
+            }
+
+            let r = f();
+            self.busy.store(false, Ordering::Release);
+            Ok(r)
+        } else {
+            unsafe { ll::os_mutex_recursive_lock(self.mutex) };
+            let r = f();
+            unsafe { ll::os_mutex_recursive_unlock(self.mutex) };
+            Ok(r)
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if !self.mutex.is_null() {
+            unsafe {
+                ll::os_mutex_recursive_destroy(self.mutex);
+            }
+        }
+    }
+}
+
+/// Error of a transaction on a shared SPI bus
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error<E> {
+    /// The transaction was started from within another one
+    Busy,
+    /// Error of the chip select pin
+    Pin(E),
+}
+
+/// Owner of a shared bus
+pub struct BusManager<B> {
+    bus: UnsafeCell<B>,
+    lock: Lock,
+    /// A device of the SPI bus is selected
+    selected: AtomicBool,
+}
+
+// the bus is only accessed while holding the lock
+unsafe impl<B> Sync for BusManager<B>
+where
+    B: Send,
+{
+}
+
+impl<B> BusManager<B> {
+    /// Takes ownership of `bus`
+    ///
+    /// Create the manager after the system threading mode is known, i.e.
+    /// not from a static initializer. Returns an error if threading is
+    /// enabled and the RTOS mutex can't be created.
+    pub fn new(bus: B) -> Result<Self, ()> {
+        Ok(BusManager {
+            bus: UnsafeCell::new(bus),
+            lock: Lock::new()?,
+            selected: AtomicBool::new(false),
+        })
+    }
+
+    /// Runs `f` with exclusive access to the bus
+    ///
+    /// Returns an error if the bus is taken, see the module documentation
+    pub fn lock<R, F>(&self, f: F) -> Result<R, ()>
+    where
+        F: FnOnce(&B) -> R,
+    {
+        // shared reference: transactions can nest when threading is enabled
+        self.lock.lock(|| f(unsafe { &*self.bus.get() }))
+    }
+
+    /// Releases the bus
+    pub fn into_inner(self) -> B {
+        self.bus.into_inner()
+    }
+}
+
+impl BusManager<I2c> {
+    /// Returns a new proxy to the bus
+    pub fn acquire<'a>(&'a self) -> I2cProxy<'a> {
+        I2cProxy { manager: self }
+    }
+}
+
+/// Proxy to a shared I2C bus
+///
+/// Transactions fail with `Error::Bus` if the bus is taken, see the module
+/// documentation
+pub struct I2cProxy<'a> {
+    manager: &'a BusManager<I2c>,
+}
+
+impl<'a> I2cProxy<'a> {
+    fn lock<F>(&self, f: F) -> Result<(), ::i2c::Error>
+    where
+        F: FnOnce(&I2c) -> Result<(), ::i2c::Error>,
+    {
+        self.manager.lock(f).unwrap_or(Err(::i2c::Error::Bus))
+    }
+}
+
+impl<'a> i2c::Write for I2cProxy<'a> {
+    type Error = ::i2c::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.lock(|bus| bus.write(address, bytes))
+    }
+}
+
+impl<'a> i2c::Read for I2cProxy<'a> {
+    type Error = ::i2c::Error;
+
+    fn read(
+        &mut self,
+        address: u8,
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.lock(|bus| bus.read(address, buffer))
+    }
+}
+
+impl<'a> i2c::WriteRead for I2cProxy<'a> {
+    type Error = ::i2c::Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.lock(|bus| bus.write_read(address, bytes, buffer))
+    }
+}
+
+/// An SPI peripheral that can be shared with `BusManager`
+pub trait SpiBus {
+    /// See `Spi::configure`
+    fn configure(&self, settings: &Settings) -> u32;
+    /// See `Spi::transfer`
+    fn transfer(&self, buffer: &mut [u8]);
+    /// See `Spi::write`
+    fn write(&self, bytes: &[u8]);
+}
+
+macro_rules! spi_bus {
+    ($Spi:ident) => {
+        impl SpiBus for $Spi {
+            fn configure(&self, settings: &Settings) -> u32 {
+                $Spi::configure(self, settings)
+            }
+
+            fn transfer(&self, buffer: &mut [u8]) {
+                $Spi::transfer(self, buffer)
+            }
+
+            fn write(&self, bytes: &[u8]) {
+                $Spi::write(self, bytes)
+            }
+        }
+    }
+}
+
+spi_bus!(Spi);
+spi_bus!(Spi1);
+
+impl<S> BusManager<S>
+where
+    S: SpiBus,
+{
+    /// Returns a new proxy to the bus that selects the device with the
+    /// active low `cs` pin and talks to it using `settings`
+    pub fn acquire_spi<'a, P>(
+        &'a self,
+        mut cs: P,
+        settings: Settings,
+    ) -> SpiProxy<'a, S, P>
+    where
+        P: OutputPin,
+    {
+        cs.set_high().ok();
+
+        SpiProxy {
+            manager: self,
+            cs,
+            settings,
+        }
+    }
+}
+
+/// Proxy to a shared SPI bus
+pub struct SpiProxy<'a, S, P>
+where
+    S: 'a,
+{
+    manager: &'a BusManager<S>,
+    cs: P,
+    settings: Settings,
+}
+
+impl<'a, S, P> SpiProxy<'a, S, P>
+where
+    S: SpiBus,
+    P: OutputPin,
+{
+    /// Changes the settings used in the next transactions
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+    }
+
+    /// Runs `f` as a single transaction: the bus is locked and the device
+    /// stays selected until `f` returns
+    ///
+    /// Fails with `Error::Busy` if called from within another transaction on
+    /// the same bus, see the module documentation
+    pub fn transaction<R, F>(&mut self, f: F) -> Result<R, Error<P::Error>>
+    where
+        F: FnOnce(&S) -> R,
+    {
+        let cs = &mut self.cs;
+        let settings = &self.settings;
+        let selected = &self.manager.selected;
+
+        self.manager
+            .lock(|bus| {
+                // only the thread holding the lock gets here, so a device
+                // is selected only if this transaction is nested
+                if selected.swap(true, Ordering::Acquire) {
+                    return Err(Error::Busy);
+                }
+
+                let r = select(bus, cs, settings, f);
+                selected.store(false, Ordering::Release);
+                r
+            })
+            .unwrap_or(Err(Error::Busy))
+    }
+
+    /// Releases the chip select pin
+    pub fn free(self) -> P {
+        self.cs
+    }
+}
+
+/// Runs `f` with the device selected
+fn select<S, P, R, F>(
+    bus: &S,
+    cs: &mut P,
+    settings: &Settings,
+    f: F,
+) -> Result<R, Error<P::Error>>
+where
+    S: SpiBus,
+    P: OutputPin,
+    F: FnOnce(&S) -> R,
+{
+    bus.configure(settings);
+    cs.set_low().map_err(Error::Pin)?;
+    let r = f(bus);
+    cs.set_high().map_err(Error::Pin)?;
+    Ok(r)
+}
+
+impl<'a, S, P> spi::Transfer<u8> for SpiProxy<'a, S, P>
+where
+    S: SpiBus,
+    P: OutputPin,
+{
+    type Error = Error<P::Error>;
+
+    fn transfer<'w>(
+        &mut self,
+        buffer: &'w mut [u8],
+    ) -> Result<&'w [u8], Self::Error> {
+        self.transaction(|bus| bus.transfer(buffer))?;
+        Ok(buffer)
+    }
+}
+
+impl<'a, S, P> spi::Write<u8> for SpiProxy<'a, S, P>
+where
+    S: SpiBus,
+    P: OutputPin,
+{
+    type Error = Error<P::Error>;
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.transaction(|bus| bus.write(bytes))
+    }
+}
@@ -0,0 +1,268 @@
+//! CAN bus on the D1 (CAN2_TX) and D2 (CAN2_RX) pins
+//!
+//! Needs an external transceiver. Frames are queued by the HAL: `transmit`
+//! and `receive` never block.
+//!
+//! ``` ignore
+//! let can = Can;
+//! can.begin(500_000);
+//!
+//! // only OBD-II responses
+//! can.add_filter(Id::Standard(0x7E8), 0x7F8).ok();
+//!
+//! can.transmit(&Frame::new(Id::Standard(0x7DF), &[2, 1, 0x0C]).unwrap())
+//!     .ok();
+//!
+//! if let Some(frame) = can.receive() {
+//!     // ..
+//! }
+//! ```
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::{mem, ptr};
+
+use ll::{self, CANMessage};
+
+use ll::HAL_CAN_Channel::CAN_D1_D2 as CHANNEL;
+
+/// Size of the receive and transmit queues, in frames
+const QUEUE_SIZE: u16 = 32;
+
+/// Set once the HAL state of the controller has been initialized
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Frame identifier
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Id {
+    /// 11-bit identifier
+    Standard(u16),
+    /// 29-bit identifier
+    Extended(u32),
+}
+
+impl Id {
+    fn raw(&self) -> u32 {
+        match *self {
+            Id::Standard(id) => u32::from(id) & 0x7FF,
+            Id::Extended(id) => id & 0x1FFF_FFFF,
+        }
+    }
+
+    fn is_extended(&self) -> bool {
+        match *self {
+            Id::Standard(_) => false,
+            Id::Extended(_) => true,
+        }
+    }
+}
+
+/// CAN frame
+#[derive(Clone, Copy)]
+pub struct Frame {
+    message: CANMessage,
+}
+
+impl Frame {
+    /// Data frame; returns `None` if `data` is longer than 8 bytes
+    pub fn new(id: Id, data: &[u8]) -> Option<Self> {
+        if data.len() > 8 {
+            return None;
+        }
+
+        let mut frame = Frame::remote(id, data.len() as u8)?;
+        frame.message.rtr = false;
+        frame.message.data[..data.len()].copy_from_slice(data);
+
+        Some(frame)
+    }
+
+    /// Remote frame requesting `len` bytes; returns `None` if `len` is
+    /// greater than 8
+    pub fn remote(id: Id, len: u8) -> Option<Self> {
+        if len > 8 {
+            return None;
+        }
+
+        Some(Frame {
+            message: CANMessage {
+                id: id.raw(),
+                size: mem::size_of::<CANMessage>() as u8,
+                extended: id.is_extended(),
+                rtr: true,
+                len,
+                data: [0; 8],
+            },
+        })
+    }
+
+    /// Identifier of the frame
+    pub fn id(&self) -> Id {
+        if self.message.extended {
+            Id::Extended(self.message.id)
+        } else {
+            Id::Standard(self.message.id as u16)
+        }
+    }
+
+    /// Returns `true` if this is a remote frame
+    pub fn is_remote(&self) -> bool {
+        self.message.rtr
+    }
+
+    /// Length of the data, or the requested length of a remote frame
+    pub fn len(&self) -> usize {
+        self.message.len as usize
+    }
+
+    /// Returns `true` if the length is zero
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Data of the frame; empty for remote frames
+    pub fn data(&self) -> &[u8] {
+        if self.message.rtr {
+            &[]
+        } else {
+            &self.message.data[..self.len()]
+        }
+    }
+}
+
+/// Error state of the controller
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum State {
+    /// Normal operation
+    Active,
+    /// Too many errors; the controller no longer signals the errors it
+    /// detects
+    ErrorPassive,
+    /// Too many transmit errors; the controller is disconnected from the
+    /// bus
+    BusOff,
+}
+
+/// CAN controller
+pub struct Can;
+
+impl Can {
+    /// Enables the CAN controller with the given bit rate, in bit/s
+    pub fn begin(&self, baud: u32) {
+        self.init(baud, 0)
+    }
+
+    /// Enables the CAN controller in loopback mode: the transmitted frames
+    /// are received back instead of being sent to the bus
+    pub fn begin_loopback(&self, baud: u32) {
+        self.init(baud, ll::CAN_TEST_MODE)
+    }
+
+    /// Disables the CAN controller
+    pub fn end(&self) {
+        unsafe { ll::HAL_CAN_End(CHANNEL, ptr::null_mut()) }
+    }
+
+    /// Returns `true` if the CAN controller is enabled
+    pub fn is_enabled(&self) -> bool {
+        unsafe { ll::HAL_CAN_Is_Enabled(CHANNEL) }
+    }
+
+    /// Queues `frame` for transmission
+    ///
+    /// Returns an error if the transmit queue is full
+    pub fn transmit(&self, frame: &Frame) -> Result<(), ()> {
+        if unsafe {
+            ll::HAL_CAN_Transmit(CHANNEL, &frame.message, ptr::null_mut())
+        } {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Takes the oldest frame from the receive queue
+    pub fn receive(&self) -> Option<Frame> {
+        let mut frame = Frame::remote(Id::Standard(0), 0).unwrap();
+
+        if unsafe {
+            ll::HAL_CAN_Receive(CHANNEL, &mut frame.message, ptr::null_mut())
+        } {
+            Some(frame)
+        } else {
+            None
+        }
+    }
+
+    /// Number of frames in the receive queue
+    pub fn available(&self) -> usize {
+        unsafe {
+            ll::HAL_CAN_Available_Messages(CHANNEL, ptr::null_mut()) as usize
+        }
+    }
+
+    /// Accepts the frames whose identifier matches `id` in the bits set in
+    /// `mask`
+    ///
+    /// Frames that match none of the filters are dropped; without filters
+    /// every frame is accepted. Returns an error if all the filter banks
+    /// are in use.
+    pub fn add_filter(&self, id: Id, mask: u32) -> Result<(), ()> {
+        let filter = if id.is_extended() {
+            ll::HAL_CAN_Filters::CAN_FILTER_EXTENDED
+        } else {
+            ll::HAL_CAN_Filters::CAN_FILTER_STANDARD
+        };
+
+        if unsafe {
+            ll::HAL_CAN_Add_Filter(
+                CHANNEL,
+                id.raw(),
+                mask,
+                filter,
+                ptr::null_mut(),
+            )
+        } {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Removes all the filters; every frame is accepted again
+    pub fn clear_filters(&self) {
+        unsafe { ll::HAL_CAN_Clear_Filters(CHANNEL, ptr::null_mut()) }
+    }
+
+    /// Current error state of the controller
+    ///
+    /// Returns `None` if the HAL reports a state unknown to this crate
+    pub fn error_status(&self) -> Option<State> {
+        match unsafe { ll::HAL_CAN_Error_Status(CHANNEL) } {
+            ll::CAN_NO_ERROR => Some(State::Active),
+            ll::CAN_ERROR_PASSIVE => Some(State::ErrorPassive),
+            ll::CAN_BUS_OFF => Some(State::BusOff),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the controller has disconnected from the bus
+    pub fn is_bus_off(&self) -> bool {
+        self.error_status() == Some(State::BusOff)
+    }
+
+    fn init(&self, baud: u32, flags: u32) {
+        unsafe {
+            // `HAL_CAN_Init` allocates the queues, the first time only
+            if !INITIALIZED.swap(true, Ordering::Relaxed) {
+                ll::HAL_CAN_Init(
+                    CHANNEL,
+                    QUEUE_SIZE,
+                    QUEUE_SIZE,
+                    ptr::null_mut(),
+                );
+            }
+
+            ll::HAL_CAN_Begin(CHANNEL, baud, flags, ptr::null_mut());
+        }
+    }
+}
@@ -1,9 +1,11 @@
-use core::cell::Cell;
+use core::cell::{Cell, UnsafeCell};
 use core::{mem, ptr};
 
+use cty::c_char;
 use photon_core::Cloud;
 use static_ref::Ref;
 
+use ll::{Spark_Data_TypeDef, c_void, time_t};
 use {String, ll};
 
 /// Registers a new cloud function with the given `name`
@@ -22,10 +24,10 @@ pub fn function(
     let mut buffer = [0; 13];
     buffer[..name.len()].copy_from_slice(name.as_bytes());
 
-    if unsafe {
-        ll::spark_function(buffer.as_ptr(), mem::transmute(f), ptr::null_mut())
-    }
-    {
+    let f: ll::p_user_function_int_str_t = unsafe { mem::transmute(f) };
+    let name = buffer.as_ptr() as *const c_char;
+
+    if unsafe { ll::spark_function(name, f, ptr::null_mut()) } {
         Ok(())
     } else {
         Err(())
@@ -34,10 +36,13 @@ pub fn function(
 
 /// Registers a new cloud variable with the given `name`
 ///
+/// The variable can be a `bool`, an `i32`, an `f64`, a `Cell` of any of
+/// those, or a `StringBuffer`
+///
 /// **NOTE** Only up to 20 cloud variables can be registered
 pub fn variable<V>(name: &str, variable: V) -> Result<(), ()>
 where
-    V: IntRef,
+    V: VariableRef,
 {
     // the max length of the variable name is 12 characters
     if name.len() > 12 {
@@ -48,11 +53,13 @@ where
     let mut buffer = [0; 13];
     buffer[..name.len()].copy_from_slice(name.as_bytes());
 
+    let ty = variable.data_type();
+
     if unsafe {
         ll::spark_variable(
-            buffer.as_ptr(),
-            variable.as_ptr() as *const _,
-            ll::Spark_Data_TypeDef::CLOUD_VAR_INT,
+            buffer.as_ptr() as *const c_char,
+            variable.into_ptr(),
+            ty,
             ptr::null_mut(),
         )
     }
@@ -63,19 +70,238 @@ where
     }
 }
 
-/// Implementation detail. Do not implement this trait.
-pub unsafe trait IntRef {
-    fn as_ptr(self) -> *const i32;
+/// Requests a time synchronization with the Particle cloud
+///
+/// The request completes asynchronously; poll the returned `SyncTime` to find
+/// out when the RTC has been updated.
+///
+/// **NOTE** This fails if the device is not connected to the cloud
+pub fn sync_time() -> Result<SyncTime, ()> {
+    if unsafe { ll::spark_sync_time(ptr::null_mut()) } {
+        Ok(SyncTime { _0: () })
+    } else {
+        Err(())
+    }
+}
+
+/// Returns the last time the RTC was synchronized with the cloud
+///
+/// Returns `None` if the time has not been synchronized since the device
+/// booted
+pub fn last_sync() -> Option<LastSync> {
+    let mut time = 0;
+    let millis =
+        unsafe { ll::spark_sync_time_last(&mut time, ptr::null_mut()) };
+
+    if millis == 0 && time == 0 {
+        None
+    } else {
+        Some(LastSync {
+            millis,
+            time,
+        })
+    }
+}
+
+/// An in-flight time synchronization request
+pub struct SyncTime {
+    _0: (),
+}
+
+impl SyncTime {
+    /// Returns `true` if the synchronization has completed
+    pub fn is_done(&self) -> bool {
+        !unsafe { ll::spark_sync_time_pending(ptr::null_mut()) }
+    }
+
+    /// Returns the result of the synchronization, if it has completed
+    pub fn poll(&self) -> Option<LastSync> {
+        if self.is_done() { last_sync() } else { None }
+    }
+}
+
+/// Information about the last time synchronization
+#[derive(Clone, Copy)]
+pub struct LastSync {
+    /// Value of `millis()` when the synchronization happened
+    pub millis: u32,
+    /// Unix time received from the cloud
+    pub time: time_t,
+}
+
+impl LastSync {
+    /// Returns the number of milliseconds elapsed since the synchronization
+    pub fn elapsed_ms(&self) -> u32 {
+        ::millis().wrapping_sub(self.millis)
+    }
+}
+
+/// Fixed capacity string that can be registered as a cloud variable
+///
+/// Holds up to `N - 1` bytes; the last byte is reserved for the null
+/// terminator.
+///
+/// `set` runs with the interrupts masked, so it can be called from any
+/// thread or interrupt handler.
+///
+/// **NOTE** With system threading enabled, a cloud request that was being
+/// served when `set` ran may see a mix of the old and the new contents
+pub struct StringBuffer<const N: usize> {
+    buffer: UnsafeCell<[u8; N]>,
+}
+
+// only written by `set`, with the interrupts masked: no other `set` and no
+// thread switch can happen during the write. The system only reads the buffer
+unsafe impl<const N: usize> Sync for StringBuffer<N> {}
+
+impl<const N: usize> Default for StringBuffer<N> {
+    fn default() -> Self {
+        StringBuffer::new()
+    }
+}
+
+impl<const N: usize> StringBuffer<N> {
+    /// Creates an empty buffer
+    pub const fn new() -> Self {
+        assert!(N > 0, "StringBuffer needs room for the null terminator");
+
+        StringBuffer {
+            buffer: UnsafeCell::new([0; N]),
+        }
+    }
+
+    /// Maximum length of the string, in bytes
+    pub fn capacity(&self) -> usize {
+        N - 1
+    }
+
+    /// Replaces the contents of the buffer with `s`
+    ///
+    /// Returns an error, and leaves the buffer untouched, if `s` is longer
+    /// than the capacity
+    pub fn set(&self, s: &str) -> Result<(), ()> {
+        if s.len() >= N {
+            return Err(());
+        }
+
+        unsafe {
+            let state = ll::HAL_disable_irq();
+
+            let buffer = &mut *self.buffer.get();
+            buffer[..s.len()].copy_from_slice(s.as_bytes());
+            buffer[s.len()] = 0;
+
+            ll::HAL_enable_irq(state);
+        }
+
+        Ok(())
+    }
+}
+
+mod private {
+    /// Keeps `VariableRef` from being implemented outside this crate
+    pub trait Sealed {}
+}
+
+/// Implementation detail. This trait is sealed.
+///
+/// # Safety
+///
+/// `into_ptr` must return a pointer that stays valid for the rest of the
+/// program and that points to a value of the type tagged by `data_type`: a
+/// `bool`, an `i32`, an `f64` or a null terminated string.
+pub unsafe trait VariableRef: private::Sealed {
+    fn data_type(&self) -> Spark_Data_TypeDef;
+    fn into_ptr(self) -> *const c_void;
+}
+
+macro_rules! variable_ref {
+    ($T:ty, $ty:ident) => {
+        impl<'a> private::Sealed for Ref<'a, $T> {}
+
+        unsafe impl<'a> VariableRef for Ref<'a, $T> {
+            fn data_type(&self) -> Spark_Data_TypeDef {
+                Spark_Data_TypeDef::$ty
+            }
+
+            fn into_ptr(self) -> *const c_void {
+                &*self as *const $T as *const c_void
+            }
+        }
+
+        impl<'a> private::Sealed for Ref<'a, Cell<$T>> {}
+
+        unsafe impl<'a> VariableRef for Ref<'a, Cell<$T>> {
+            fn data_type(&self) -> Spark_Data_TypeDef {
+                Spark_Data_TypeDef::$ty
+            }
+
+            fn into_ptr(self) -> *const c_void {
+                self.as_ptr() as *const c_void
+            }
+        }
+    }
 }
 
-unsafe impl<'a> IntRef for Ref<'a, i32> {
-    fn as_ptr(self) -> *const i32 {
-        &*self
+variable_ref!(bool, CLOUD_VAR_BOOLEAN);
+variable_ref!(i32, CLOUD_VAR_INT);
+variable_ref!(f64, CLOUD_VAR_DOUBLE);
+
+impl<'a, const N: usize> private::Sealed for Ref<'a, StringBuffer<N>> {}
+
+unsafe impl<'a, const N: usize> VariableRef for Ref<'a, StringBuffer<N>> {
+    fn data_type(&self) -> Spark_Data_TypeDef {
+        Spark_Data_TypeDef::CLOUD_VAR_STRING
+    }
+
+    fn into_ptr(self) -> *const c_void {
+        self.buffer.get() as *const c_void
     }
 }
 
-unsafe impl<'a> IntRef for Ref<'a, Cell<i32>> {
-    fn as_ptr(self) -> *const i32 {
-        (*self).as_ptr()
+#[cfg(test)]
+mod tests {
+    use core::str;
+
+    use super::*;
+
+    fn contents<const N: usize>(buffer: &StringBuffer<N>) -> &[u8; N] {
+        unsafe { &*buffer.buffer.get() }
+    }
+
+    #[test]
+    fn string_buffer() {
+        let buffer = StringBuffer::<8>::new();
+        assert_eq!(buffer.capacity(), 7);
+        assert_eq!(contents(&buffer), &[0; 8]);
+
+        assert_eq!(buffer.set("1234567"), Ok(()));
+        assert_eq!(contents(&buffer), b"1234567\0");
+
+        // shorter strings are null terminated; the tail is left as is
+        assert_eq!(buffer.set("abc"), Ok(()));
+        assert_eq!(&contents(&buffer)[..4], b"abc\0");
+        let end = contents(&buffer).iter().position(|&b| b == 0);
+        let end = end.unwrap();
+        assert_eq!(str::from_utf8(&contents(&buffer)[..end]), Ok("abc"));
+
+        assert_eq!(buffer.set(""), Ok(()));
+        assert_eq!(contents(&buffer)[0], 0);
+    }
+
+    #[test]
+    fn string_buffer_too_long() {
+        let buffer = StringBuffer::<4>::new();
+        assert_eq!(buffer.set("abc"), Ok(()));
+
+        // left untouched
+        assert_eq!(buffer.set("abcd"), Err(()));
+        assert_eq!(buffer.set("too long"), Err(()));
+        assert_eq!(contents(&buffer), b"abc\0");
+
+        let empty = StringBuffer::<1>::default();
+        assert_eq!(empty.capacity(), 0);
+        assert_eq!(empty.set("a"), Err(()));
+        assert_eq!(empty.set(""), Ok(()));
     }
 }
@@ -0,0 +1,493 @@
+//! Hardware I2C on the D0 (SDA) and D1 (SCL) pins
+//!
+//! The HAL buffers a whole transaction, so reads and writes are limited to
+//! `I2C_BUFFER_LENGTH` (32) bytes each.
+//!
+//! The peripheral works either as a master, `I2c`, or as a slave,
+//! `I2cSlave`:
+//!
+//! ``` ignore
+//! fn on_receive(bytes: &[u8]) {
+//!     // command sent by the master
+//! }
+//!
+//! fn on_request(reply: &mut Reply) {
+//!     reply.write(&[STATUS]).ok();
+//! }
+//!
+//! let slave = I2cSlave::begin(0x42, on_receive, on_request);
+//! ```
+//!
+//! `I2c::scan` lists the devices on the bus and `I2c::recover` frees a bus
+//! that a slave holds busy, e.g. after a reset in the middle of a read.
+
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cty::c_int;
+use hal::blocking::i2c;
+use ll::{self, I2C_BUFFER_LENGTH};
+
+use ll::HAL_I2C_Interface::HAL_I2C_INTERFACE1 as INTERFACE;
+
+/// Standard mode clock speed, in Hz
+pub const STANDARD_MODE: u32 = 100_000;
+
+/// Fast mode clock speed, in Hz
+pub const FAST_MODE: u32 = 400_000;
+
+/// I2C errors
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// No device acknowledged the address
+    AddressNack,
+    /// The device didn't acknowledge a data byte
+    DataNack,
+    /// The bus is busy or the transfer timed out
+    Bus,
+    /// The transfer doesn't fit in the HAL buffers
+    Overflow,
+}
+
+/// SDA pin, D0
+const SDA: ll::pin_t = 0;
+
+/// SCL pin, D1
+const SCL: ll::pin_t = 1;
+
+/// Half of a 100 kHz clock period, in microseconds
+const HALF_PERIOD: u32 = 5;
+
+/// Lets the pull-up take `pin` high, or the bus keep it low
+fn release(pin: ll::pin_t) {
+    unsafe { ll::HAL_Pin_Mode(pin, ll::PinMode::INPUT_PULLUP) }
+}
+
+/// Drives `pin` low
+fn pull_low(pin: ll::pin_t) {
+    unsafe {
+        ll::HAL_GPIO_Write(pin, 0);
+        ll::HAL_Pin_Mode(pin, ll::PinMode::OUTPUT);
+    }
+}
+
+fn is_high(pin: ll::pin_t) -> bool {
+    unsafe { ll::HAL_GPIO_Read(pin) != 0 }
+}
+
+/// Set of the 7-bit addresses that responded to a scan
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Devices([u32; 4]);
+
+impl Devices {
+    /// Returns `true` if the device at `address` responded
+    pub fn contains(&self, address: u8) -> bool {
+        address < 128 &&
+            self.0[address as usize / 32] & 1 << (address % 32) != 0
+    }
+
+    /// Number of devices that responded
+    pub fn len(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Returns `true` if no device responded
+    pub fn is_empty(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    /// Iterates over the addresses of the devices, in ascending order
+    pub fn iter(&self) -> Iter {
+        Iter {
+            devices: *self,
+            next: 0,
+        }
+    }
+
+    fn insert(&mut self, address: u8) {
+        self.0[address as usize / 32] |= 1 << (address % 32);
+    }
+}
+
+/// Iterator over the addresses of `Devices`
+pub struct Iter {
+    devices: Devices,
+    next: u8,
+}
+
+impl Iterator for Iter {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        while self.next < 128 {
+            let address = self.next;
+            self.next += 1;
+
+            if self.devices.contains(address) {
+                return Some(address);
+            }
+        }
+
+        None
+    }
+}
+
+/// Set once the HAL state of the peripheral has been initialized
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Initializes the HAL state of the peripheral, the first time only
+fn init() {
+    if !INITIALIZED.swap(true, Ordering::Relaxed) {
+        unsafe { ll::HAL_I2C_Init(INTERFACE, ptr::null_mut()) }
+    }
+}
+
+/// Hardware I2C master
+pub struct I2c;
+
+impl I2c {
+    /// Enables the I2C peripheral as a master with the given clock speed, in
+    /// Hz
+    pub fn begin(&self, speed: u32) {
+        init();
+
+        unsafe {
+            ll::HAL_I2C_Set_Speed(INTERFACE, speed, ptr::null_mut());
+            ll::HAL_I2C_Begin(
+                INTERFACE,
+                ll::I2C_Mode::I2C_MODE_MASTER,
+                0,
+                ptr::null_mut(),
+            );
+        }
+    }
+
+    /// Disables the I2C peripheral
+    pub fn end(&self) {
+        unsafe { ll::HAL_I2C_End(INTERFACE, ptr::null_mut()) }
+    }
+
+    /// Returns `true` if the I2C peripheral is enabled
+    pub fn is_enabled(&self) -> bool {
+        unsafe { ll::HAL_I2C_Is_Enabled(INTERFACE, ptr::null_mut()) }
+    }
+
+    /// Writes `bytes` to the device at `address`
+    pub fn write(&self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.transmit(address, bytes, true)
+    }
+
+    /// Reads `buffer.len()` bytes from the device at `address`
+    pub fn read(&self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        if buffer.len() > I2C_BUFFER_LENGTH {
+            return Err(Error::Overflow);
+        }
+
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        let n = unsafe {
+            ll::HAL_I2C_Request_Data(
+                INTERFACE,
+                address,
+                buffer.len() as u8,
+                1,
+                ptr::null_mut(),
+            )
+        };
+
+        // the HAL receives nothing if the address is not acknowledged
+        if n == 0 {
+            return Err(Error::AddressNack);
+        }
+
+        if n as usize != buffer.len() {
+            return Err(Error::Bus);
+        }
+
+        for byte in buffer {
+            let data =
+                unsafe { ll::HAL_I2C_Read_Data(INTERFACE, ptr::null_mut()) };
+            *byte = data as u8;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `bytes` to the device at `address` and then, after a repeated
+    /// start, reads `buffer.len()` bytes from it
+    pub fn write_read(
+        &self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        if buffer.len() > I2C_BUFFER_LENGTH {
+            return Err(Error::Overflow);
+        }
+
+        self.transmit(address, bytes, false)?;
+        self.read(address, buffer)
+    }
+
+    /// Probes every non reserved 7-bit address (0x08 to 0x77) with an empty
+    /// write and returns the ones that were acknowledged
+    pub fn scan(&self) -> Devices {
+        let mut devices = Devices::default();
+
+        for address in 0x08..0x78 {
+            if self.transmit(address, &[], true).is_ok() {
+                devices.insert(address);
+            }
+        }
+
+        devices
+    }
+
+    /// Releases a bus whose SDA line is held low by a slave and re-enables
+    /// the peripheral with the given clock speed, in Hz
+    ///
+    /// The peripheral is disabled and SCL is clocked through GPIO, up to 9
+    /// times, until the slave lets go of SDA; then a STOP condition is
+    /// generated. The lines are driven as open drain: they're only ever
+    /// pulled low, and released to the pull-ups otherwise. Returns an error,
+    /// without generating the STOP, if SDA is still low.
+    pub fn recover(&self, speed: u32) -> Result<(), Error> {
+        self.end();
+
+        release(SDA);
+        release(SCL);
+        ::delay_us(HALF_PERIOD);
+
+        let mut pulses = 0;
+        while !is_high(SDA) && pulses < 9 {
+            pull_low(SCL);
+            ::delay_us(HALF_PERIOD);
+            release(SCL);
+            ::delay_us(HALF_PERIOD);
+            pulses += 1;
+        }
+
+        let released = is_high(SDA);
+
+        if released {
+            // STOP: SDA rises while SCL is high
+            pull_low(SCL);
+            pull_low(SDA);
+            ::delay_us(HALF_PERIOD);
+            release(SCL);
+            ::delay_us(HALF_PERIOD);
+            release(SDA);
+            ::delay_us(HALF_PERIOD);
+        }
+
+        self.begin(speed);
+
+        if released {
+            Ok(())
+        } else {
+            Err(Error::Bus)
+        }
+    }
+
+    fn transmit(
+        &self,
+        address: u8,
+        bytes: &[u8],
+        stop: bool,
+    ) -> Result<(), Error> {
+        if bytes.len() > I2C_BUFFER_LENGTH {
+            return Err(Error::Overflow);
+        }
+
+        unsafe {
+            ll::HAL_I2C_Begin_Transmission(INTERFACE, address, ptr::null_mut());
+
+            for &byte in bytes {
+                ll::HAL_I2C_Write_Data(INTERFACE, byte, ptr::null_mut());
+            }
+
+            match ll::HAL_I2C_End_Transmission(
+                INTERFACE,
+                stop as u8,
+                ptr::null_mut(),
+            ) {
+                0 => Ok(()),
+                3 => Err(Error::AddressNack),
+                4 => Err(Error::DataNack),
+                _ => Err(Error::Bus),
+            }
+        }
+    }
+}
+
+impl i2c::Write for I2c {
+    type Error = Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        I2c::write(self, address, bytes)
+    }
+}
+
+impl i2c::Read for I2c {
+    type Error = Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        I2c::read(self, address, buffer)
+    }
+}
+
+impl i2c::WriteRead for I2c {
+    type Error = Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        I2c::write_read(self, address, bytes, buffer)
+    }
+}
+
+/// Handler of the data written by the master, see `I2cSlave`
+pub type ReceiveHandler = fn(bytes: &[u8]);
+
+/// Handler of the reads of the master, see `I2cSlave`
+pub type RequestHandler = fn(reply: &mut Reply);
+
+/// Handler shared with the I2C interrupt handler
+struct Handler<T>(UnsafeCell<Option<T>>);
+
+// only written while the slave is disabled
+unsafe impl<T> Sync for Handler<T> {}
+
+static ON_RECEIVE: Handler<ReceiveHandler> = Handler(UnsafeCell::new(None));
+static ON_REQUEST: Handler<RequestHandler> = Handler(UnsafeCell::new(None));
+
+/// Reply to a read of the master
+pub struct Reply {
+    len: usize,
+}
+
+impl Reply {
+    /// Appends `bytes` to the reply
+    ///
+    /// Returns an error, and appends nothing, if the reply would be longer
+    /// than `I2C_BUFFER_LENGTH` bytes
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        if self.len + bytes.len() > I2C_BUFFER_LENGTH {
+            return Err(());
+        }
+
+        for &byte in bytes {
+            unsafe {
+                ll::HAL_I2C_Write_Data(INTERFACE, byte, ptr::null_mut());
+            }
+        }
+        self.len += bytes.len();
+
+        Ok(())
+    }
+
+    /// Length of the reply so far
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if nothing has been written to the reply
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Hardware I2C slave
+///
+/// The handlers run in interrupt context and must return quickly
+pub struct I2cSlave {
+    address: u8,
+}
+
+impl I2cSlave {
+    /// Enables the I2C peripheral as a slave at the 7-bit `address`
+    ///
+    /// `on_receive` gets the data of every write of the master and
+    /// `on_request` provides the data of every read. If the peripheral is
+    /// already enabled, as a master or as a slave, it's disabled first
+    pub fn begin(
+        address: u8,
+        on_receive: ReceiveHandler,
+        on_request: RequestHandler,
+    ) -> Self {
+        init();
+
+        unsafe {
+            // the interrupt handler must not run while the handlers change
+            if ll::HAL_I2C_Is_Enabled(INTERFACE, ptr::null_mut()) {
+                ll::HAL_I2C_End(INTERFACE, ptr::null_mut());
+            }
+
+            *ON_RECEIVE.0.get() = Some(on_receive);
+            *ON_REQUEST.0.get() = Some(on_request);
+
+            ll::HAL_I2C_Set_Callback_On_Receive(
+                INTERFACE,
+                receive,
+                ptr::null_mut(),
+            );
+            ll::HAL_I2C_Set_Callback_On_Request(
+                INTERFACE,
+                request,
+                ptr::null_mut(),
+            );
+            ll::HAL_I2C_Begin(
+                INTERFACE,
+                ll::I2C_Mode::I2C_MODE_SLAVE,
+                address,
+                ptr::null_mut(),
+            );
+        }
+
+        I2cSlave { address }
+    }
+
+    /// The address the slave responds to
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Disables the I2C peripheral
+    pub fn end(self) {
+        unsafe {
+            ll::HAL_I2C_End(INTERFACE, ptr::null_mut());
+
+            *ON_RECEIVE.0.get() = None;
+            *ON_REQUEST.0.get() = None;
+        }
+    }
+}
+
+extern "C" fn receive(n: c_int) {
+    let mut buffer = [0; I2C_BUFFER_LENGTH];
+    let mut len = 0;
+
+    while len < n as usize && len < I2C_BUFFER_LENGTH {
+        match unsafe { ll::HAL_I2C_Read_Data(INTERFACE, ptr::null_mut()) } {
+            -1 => break,
+            byte => buffer[len] = byte as u8,
+        }
+        len += 1;
+    }
+
+    if let Some(handler) = unsafe { *ON_RECEIVE.0.get() } {
+        handler(&buffer[..len]);
+    }
+}
+
+extern "C" fn request() {
+    if let Some(handler) = unsafe { *ON_REQUEST.0.get() } {
+        handler(&mut Reply { len: 0 });
+    }
+}
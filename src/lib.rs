@@ -1,17 +1,51 @@
 //! Low level bindings to the Particle HAL
 
 #![allow(non_camel_case_types)]
+// `Result<_, ()>` is used throughout the crate when there's a single failure
+// cause, documented on each function
+#![allow(clippy::result_unit_err)]
 #![deny(warnings)]
 #![no_std]
 
 extern crate cty;
+extern crate embedded_hal as hal;
+extern crate embedded_io as io;
+extern crate nb;
 extern crate photon_core;
 extern crate static_ref;
+#[cfg(test)]
+extern crate std;
 
-use core::{ops, slice};
+use core::cell::UnsafeCell;
+use core::convert::Infallible;
+use core::{cmp, fmt, mem, ops, ptr, slice};
 
+#[macro_use]
+mod macros;
+
+pub mod bus;
+pub mod can;
 pub mod cloud;
+pub mod i2c;
 pub mod ll;
+pub mod modbus;
+pub mod profile;
+pub mod schedule;
+pub mod shell;
+pub mod spi;
+pub mod time;
+pub mod usart;
+pub mod usb;
+pub mod ymodem;
+
+#[cfg(test)]
+mod mock;
+
+pub use can::Can;
+pub use i2c::{I2c, I2cSlave};
+pub use spi::{Spi, Spi1};
+pub use usart::{Serial1, Serial2};
+pub use usb::Usb;
 
 use cty::{c_char, c_uchar, c_uint};
 
@@ -31,26 +65,375 @@ impl ops::Deref for String {
     type Target = [u8];
 
     fn deref(&self) -> &[u8] {
-        unsafe { slice::from_raw_parts(self.buffer, self.len as usize) }
+        unsafe {
+            slice::from_raw_parts(self.buffer as *const u8, self.len as usize)
+        }
     }
 }
 
-pub struct UsbSerial;
+/// Default size of the receive buffer of the USB serial ports
+pub const USB_RX_BUFFER_SIZE: usize = 256;
 
-impl UsbSerial {
-    /// Enables the serial channel with the specified `baud_rate`
-    pub fn begin(&self, baud_rate: u32) {
-        unsafe { ll::USB_USART_Init(baud_rate) }
-    }
+/// Default size of the transmit buffer of the USB serial ports
+pub const USB_TX_BUFFER_SIZE: usize = 256;
 
-    /// Writes binary data to the serial port
-    pub fn write(&self, byte: u8) {
-        unsafe {
-            ll::USB_USART_Send_Data(byte);
+/// Default USB serial buffer, shared with the USB interrupt handler
+struct UsbBuffer<T>(UnsafeCell<T>);
+
+// the HAL is the only one that accesses the buffer contents
+unsafe impl<T> Sync for UsbBuffer<T> {}
+
+macro_rules! usb_serial {
+    ($(#[$attr:meta])* $Serial:ident, $serial:ident, $RX:ident, $TX:ident) => {
+        static $RX: UsbBuffer<[u8; USB_RX_BUFFER_SIZE]> =
+            UsbBuffer(UnsafeCell::new([0; USB_RX_BUFFER_SIZE]));
+        static $TX: UsbBuffer<[u8; USB_TX_BUFFER_SIZE]> =
+            UsbBuffer(UnsafeCell::new([0; USB_TX_BUFFER_SIZE]));
+
+        $(#[$attr])*
+        pub struct $Serial;
+
+        impl $Serial {
+            /// Enables the serial channel with the specified `baud_rate`,
+            /// using the default buffers
+            pub fn begin(&self, baud_rate: u32) {
+                unsafe {
+                    self.init(
+                        (*$RX.0.get()).as_mut_ptr(),
+                        USB_RX_BUFFER_SIZE,
+                        (*$TX.0.get()).as_mut_ptr(),
+                        USB_TX_BUFFER_SIZE,
+                    );
+                    self.begin_(baud_rate);
+                }
+            }
+
+            /// Enables the serial channel with the specified `baud_rate`,
+            /// using the given receive and transmit buffers
+            ///
+            /// # Panics
+            ///
+            /// If either buffer is longer than `u16::MAX` bytes
+            pub fn begin_with_buffers(
+                &self,
+                baud_rate: u32,
+                rx: &'static mut [u8],
+                tx: &'static mut [u8],
+            ) {
+                assert!(rx.len() <= usize::from(u16::MAX));
+                assert!(tx.len() <= usize::from(u16::MAX));
+
+                unsafe {
+                    self.init(
+                        rx.as_mut_ptr(),
+                        rx.len(),
+                        tx.as_mut_ptr(),
+                        tx.len(),
+                    );
+                    self.begin_(baud_rate);
+                }
+            }
+
+            unsafe fn init(
+                &self,
+                rx: *mut u8,
+                rx_len: usize,
+                tx: *mut u8,
+                tx_len: usize,
+            ) {
+                let config = ll::HAL_USB_USART_Config {
+                    size: mem::size_of::<ll::HAL_USB_USART_Config>() as u16,
+                    rx_buffer: rx,
+                    rx_buffer_size: rx_len as u16,
+                    tx_buffer: tx,
+                    tx_buffer_size: tx_len as u16,
+                };
+
+                ll::HAL_USB_USART_Init(
+                    ll::HAL_USB_USART_Serial::$serial,
+                    &config,
+                );
+            }
+
+            unsafe fn begin_(&self, baud_rate: u32) {
+                ll::HAL_USB_USART_Begin(
+                    ll::HAL_USB_USART_Serial::$serial,
+                    baud_rate,
+                    ptr::null_mut(),
+                )
+            }
+
+            /// Disables the serial channel
+            pub fn end(&self) {
+                unsafe {
+                    ll::HAL_USB_USART_End(ll::HAL_USB_USART_Serial::$serial)
+                }
+            }
+
+            /// Returns `true` if the serial channel is enabled
+            pub fn is_enabled(&self) -> bool {
+                unsafe {
+                    ll::HAL_USB_USART_Is_Enabled(
+                        ll::HAL_USB_USART_Serial::$serial,
+                    )
+                }
+            }
+
+            /// Returns `true` if a host has opened the serial port
+            pub fn is_connected(&self) -> bool {
+                unsafe {
+                    ll::HAL_USB_USART_Is_Connected(
+                        ll::HAL_USB_USART_Serial::$serial,
+                    )
+                }
+            }
+
+            /// Writes binary data to the serial port
+            pub fn write(&self, byte: u8) {
+                unsafe {
+                    ll::HAL_USB_USART_Send_Data(
+                        ll::HAL_USB_USART_Serial::$serial,
+                        byte,
+                    );
+                }
+            }
+
+            /// Writes all the `bytes` to the serial port
+            ///
+            /// This blocks while the transmit buffer is full. The data is
+            /// discarded if the host hasn't opened the port
+            pub fn write_all(&self, bytes: &[u8]) {
+                let mut bytes = bytes;
+                while !bytes.is_empty() {
+                    let n = match unsafe {
+                        ll::HAL_USB_USART_Available_Data_For_Write(
+                            ll::HAL_USB_USART_Serial::$serial,
+                        )
+                    } {
+                        n if n < 0 => return,
+                        n => cmp::min(n as usize, bytes.len()),
+                    };
+                    let (head, tail) = bytes.split_at(n);
+                    for &byte in head {
+                        self.write(byte);
+                    }
+                    bytes = tail;
+                }
+            }
+
+            /// Returns the number of bytes that can be written without
+            /// blocking
+            pub fn available_for_write(&self) -> usize {
+                match unsafe {
+                    ll::HAL_USB_USART_Available_Data_For_Write(
+                        ll::HAL_USB_USART_Serial::$serial,
+                    )
+                } {
+                    n if n > 0 => n as usize,
+                    _ => 0,
+                }
+            }
+
+            /// Returns the baud rate requested by the host
+            pub fn baud_rate(&self) -> u32 {
+                unsafe {
+                    ll::HAL_USB_USART_Baud_Rate(
+                        ll::HAL_USB_USART_Serial::$serial,
+                    )
+                }
+            }
+
+            /// Returns the number of bytes available for reading
+            pub fn available(&self) -> usize {
+                match unsafe {
+                    ll::HAL_USB_USART_Available_Data(
+                        ll::HAL_USB_USART_Serial::$serial,
+                    )
+                } {
+                    n if n > 0 => n as usize,
+                    _ => 0,
+                }
+            }
+
+            /// Reads one byte from the serial port
+            ///
+            /// Returns `None` if no data is available
+            pub fn read(&self) -> Option<u8> {
+                match unsafe {
+                    ll::HAL_USB_USART_Receive_Data(
+                        ll::HAL_USB_USART_Serial::$serial,
+                        0,
+                    )
+                } {
+                    -1 => None,
+                    byte => Some(byte as u8),
+                }
+            }
+
+            /// Returns the next byte that `read` will return without
+            /// removing it from the receive buffer
+            pub fn peek(&self) -> Option<u8> {
+                match unsafe {
+                    ll::HAL_USB_USART_Receive_Data(
+                        ll::HAL_USB_USART_Serial::$serial,
+                        1,
+                    )
+                } {
+                    -1 => None,
+                    byte => Some(byte as u8),
+                }
+            }
+
+            /// Reads the available bytes into `buffer`
+            ///
+            /// This doesn't block. Returns the number of bytes read
+            pub fn read_bytes(&self, buffer: &mut [u8]) -> usize {
+                let mut n = 0;
+                for slot in buffer {
+                    match self.read() {
+                        Some(byte) => *slot = byte,
+                        None => break,
+                    }
+                    n += 1;
+                }
+                n
+            }
+
+            /// Waits until all the outgoing data has been transmitted
+            pub fn flush(&self) {
+                unsafe {
+                    ll::HAL_USB_USART_Flush_Data(
+                        ll::HAL_USB_USART_Serial::$serial,
+                    )
+                }
+            }
+        }
+
+        impl fmt::Write for $Serial {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                (&*self).write_str(s)
+            }
+        }
+
+        impl<'a> fmt::Write for &'a $Serial {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.write_all(s.as_bytes());
+                Ok(())
+            }
+        }
+
+        impl hal::serial::Read<u8> for $Serial {
+            type Error = Infallible;
+
+            fn read(&mut self) -> nb::Result<u8, Infallible> {
+                $Serial::read(self).ok_or(nb::Error::WouldBlock)
+            }
+        }
+
+        impl hal::serial::Write<u8> for $Serial {
+            type Error = Infallible;
+
+            fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+                if self.is_connected() && self.available_for_write() == 0 {
+                    return Err(nb::Error::WouldBlock);
+                }
+
+                $Serial::write(self, byte);
+                Ok(())
+            }
+
+            fn flush(&mut self) -> nb::Result<(), Infallible> {
+                $Serial::flush(self);
+                Ok(())
+            }
+        }
+
+        impl io::ErrorType for $Serial {
+            type Error = Infallible;
+        }
+
+        impl io::Read for $Serial {
+            fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Infallible> {
+                if buffer.is_empty() {
+                    return Ok(0);
+                }
+
+                loop {
+                    match self.read_bytes(buffer) {
+                        0 => {}
+                        n => return Ok(n),
+                    }
+                }
+            }
+        }
+
+        impl io::ReadReady for $Serial {
+            fn read_ready(&mut self) -> Result<bool, Infallible> {
+                Ok(self.available() != 0)
+            }
+        }
+
+        impl io::Write for $Serial {
+            /// The data is discarded if the host hasn't opened the port
+            fn write(&mut self, buffer: &[u8]) -> Result<usize, Infallible> {
+                if buffer.is_empty() {
+                    return Ok(0);
+                }
+
+                loop {
+                    match unsafe {
+                        ll::HAL_USB_USART_Available_Data_For_Write(
+                            ll::HAL_USB_USART_Serial::$serial,
+                        )
+                    } {
+                        n if n < 0 => return Ok(buffer.len()),
+                        0 => {}
+                        n => {
+                            let n = cmp::min(n as usize, buffer.len());
+                            for &byte in &buffer[..n] {
+                                $Serial::write(self, byte);
+                            }
+                            return Ok(n);
+                        }
+                    }
+                }
+            }
+
+            fn flush(&mut self) -> Result<(), Infallible> {
+                $Serial::flush(self);
+                Ok(())
+            }
+        }
+
+        impl io::WriteReady for $Serial {
+            fn write_ready(&mut self) -> Result<bool, Infallible> {
+                Ok(self.available_for_write() != 0)
+            }
         }
     }
 }
 
+usb_serial!(
+    /// USB serial channel (`Serial`)
+    UsbSerial,
+    HAL_USB_USART_SERIAL,
+    USB_SERIAL_RX,
+    USB_SERIAL_TX
+);
+
+usb_serial!(
+    /// Second USB serial channel (`USBSerial1`)
+    UsbSerial1,
+    HAL_USB_USART_SERIAL1,
+    USB_SERIAL1_RX,
+    USB_SERIAL1_TX
+);
+
+#[doc(hidden)]
+pub fn __serial_print(args: fmt::Arguments) {
+    fmt::Write::write_fmt(&mut UsbSerial, args).ok();
+}
+
 pub enum PinMode {
     Input,
     InputPulldown,
@@ -123,6 +506,20 @@ macro_rules! digital_write {
                 }
             }
         }
+
+        impl hal::digital::v2::OutputPin for $pin {
+            type Error = Infallible;
+
+            fn set_low(&mut self) -> Result<(), Infallible> {
+                self.low();
+                Ok(())
+            }
+
+            fn set_high(&mut self) -> Result<(), Infallible> {
+                self.high();
+                Ok(())
+            }
+        }
     }
 }
 
@@ -157,3 +554,8 @@ pub fn delay_us(us: u32) {
 pub fn micros() -> u32 {
     unsafe { ll::HAL_Timer_Get_Micro_Seconds() }
 }
+
+/// Returns the number of milliseconds since the device booted
+pub fn millis() -> u32 {
+    unsafe { ll::HAL_Timer_Get_Milli_Seconds() }
+}
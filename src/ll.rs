@@ -1,11 +1,88 @@
 //! Low level bindings to the HAL
 
+use core::mem;
+
 use String;
-use cty::{c_char, c_int, c_ulong, c_uint, int32_t, uint16_t, uint32_t, uint8_t};
+use cty::{c_char, c_int, c_long, c_ulong, c_uint, int32_t, uint16_t, uint32_t,
+          uint8_t};
 
 pub type pin_t = u16;
 pub type p_user_function_int_str_t = extern "C" fn(&String) -> c_int;
 pub type system_tick_t = u32;
+pub type os_mutex_recursive_t = *mut c_void;
+pub type time_t = c_long;
+pub type system_event_t = u64;
+pub type system_event_handler_t = extern "C" fn(event: system_event_t,
+                                                data: c_int,
+                                                pointer: *mut c_void);
+
+pub type ymodem_serial_flash_update_handler =
+    extern "C" fn(serial: *mut Stream,
+                  file: &mut FileTransfer_Descriptor,
+                  _: *mut c_void) -> bool;
+
+pub type HAL_USB_Vendor_Request_Callback =
+    extern "C" fn(req: *mut HAL_USB_SetupRequest, p: *mut c_void) -> uint8_t;
+pub type usb_request_app_handler_type =
+    extern "C" fn(req: *mut USBRequest, _: *mut c_void) -> bool;
+
+/// `system_thread_get_state` value when `SYSTEM_THREAD(ENABLED)`
+pub const FEATURE_ENABLED: c_int = 1;
+
+/// `time_changed` system event
+pub const TIME_CHANGED: system_event_t = 1 << 14;
+
+/// `Spark_Finish_Firmware_Update` flags
+pub const UPDATE_FLAG_SUCCESS: uint32_t = 1;
+pub const UPDATE_FLAG_VALIDATE_ONLY: uint32_t = 2;
+
+/// Wiring's `Stream` class
+pub enum Stream {}
+
+#[repr(u32)]
+pub enum FileTransfer_Store {
+    FIRMWARE = 0,
+    SYSTEM = 1,
+    APPLICATION_DATA = 2,
+}
+
+#[repr(C)]
+pub struct FileTransfer_Descriptor {
+    pub file_length: uint32_t,
+    /// `0` means the default address of `store`
+    pub file_address: uint32_t,
+    pub chunk_address: uint32_t,
+    pub chunk_size: uint16_t,
+    pub store: FileTransfer_Store,
+}
+
+#[allow(non_snake_case)]
+#[repr(C)]
+pub struct HAL_USB_SetupRequest {
+    pub bmRequestType: uint8_t,
+    pub bRequest: uint8_t,
+    pub wValue: uint16_t,
+    pub wIndex: uint16_t,
+    pub wLength: uint16_t,
+    pub data: *mut uint8_t,
+}
+
+/// `USBRequest::type` of the requests meant for the application
+pub const USB_REQUEST_CUSTOM: c_int = 10;
+
+/// Size of the `USBRequest` data buffer
+pub const USB_REQUEST_BUFFER_SIZE: usize = 512;
+
+#[repr(C)]
+pub struct USBRequest {
+    /// size of this struct
+    pub size: usize,
+    pub ty: c_int,
+    /// request on entry, reply on exit
+    pub data: *mut c_char,
+    pub request_size: usize,
+    pub reply_size: usize,
+}
 
 #[repr(C)]
 pub struct spark_variable_t {
@@ -42,6 +119,179 @@ pub enum PinMode {
     PIN_MODE_NONE = 255,
 }
 
+#[repr(u32)]
+pub enum HAL_CAN_Channel {
+    CAN_D1_D2 = 0,
+}
+
+#[repr(u32)]
+pub enum HAL_CAN_Filters {
+    CAN_FILTER_STANDARD = 0,
+    CAN_FILTER_EXTENDED = 1,
+}
+
+// `HAL_CAN_Error_Status` values (`HAL_CAN_Errors`)
+pub const CAN_NO_ERROR: uint32_t = 0;
+pub const CAN_ERROR_PASSIVE: uint32_t = 1;
+pub const CAN_BUS_OFF: uint32_t = 2;
+
+/// `HAL_CAN_Begin` flag: loopback mode, frames are received back and not
+/// sent to the bus
+pub const CAN_TEST_MODE: uint32_t = 1;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct CANMessage {
+    pub id: uint32_t,
+    /// `sizeof(CANMessage)`, set by the caller
+    pub size: uint8_t,
+    pub extended: bool,
+    pub rtr: bool,
+    pub len: uint8_t,
+    pub data: [uint8_t; 8],
+}
+
+const _: () = assert!(
+    mem::size_of::<CANMessage>() == 16 &&
+        mem::offset_of!(CANMessage, size) == 4 &&
+        mem::offset_of!(CANMessage, len) == 7 &&
+        mem::offset_of!(CANMessage, data) == 8
+);
+
+#[repr(u32)]
+pub enum HAL_I2C_Interface {
+    HAL_I2C_INTERFACE1 = 0,
+}
+
+#[repr(u32)]
+pub enum I2C_Mode {
+    I2C_MODE_MASTER = 0,
+    I2C_MODE_SLAVE = 1,
+}
+
+/// Size of the I2C receive and transmit buffers
+pub const I2C_BUFFER_LENGTH: usize = 32;
+
+#[derive(Clone, Copy)]
+#[repr(u32)]
+pub enum HAL_SPI_Interface {
+    HAL_SPI_INTERFACE1 = 0,
+    HAL_SPI_INTERFACE2 = 1,
+}
+
+#[repr(u32)]
+pub enum SPI_Mode {
+    SPI_MODE_MASTER = 0,
+    SPI_MODE_SLAVE = 1,
+}
+
+/// Makes `HAL_SPI_Begin_Ext` use the default SS pin of the interface
+pub const SPI_DEFAULT_SS: pin_t = 0xFFFF;
+
+// `HAL_SPI_Set_Bit_Order` values
+pub const LSBFIRST: uint8_t = 0;
+pub const MSBFIRST: uint8_t = 1;
+
+// `HAL_SPI_Set_Data_Mode` values
+pub const SPI_MODE0: uint8_t = 0x00;
+pub const SPI_MODE1: uint8_t = 0x01;
+pub const SPI_MODE2: uint8_t = 0x02;
+pub const SPI_MODE3: uint8_t = 0x03;
+
+/// `HAL_SPI_Set_Clock_Divider` value of the `/2` divider; each following
+/// power of two, up to `/256`, adds `SPI_CLOCK_DIV_STEP`
+pub const SPI_CLOCK_DIV2: uint8_t = 0x00;
+pub const SPI_CLOCK_DIV_STEP: uint8_t = 0x08;
+
+// `hal_spi_info_t.version` values: `HAL_SPI_Info` fills `system_clock` for
+// any version, the rest of the settings up to `data_mode` from version 1 and
+// `ss_pin` from version 2
+pub const HAL_SPI_INFO_VERSION_1: uint16_t = 11;
+pub const HAL_SPI_INFO_VERSION_2: uint16_t = 12;
+
+#[repr(C)]
+pub struct hal_spi_info_t {
+    pub version: uint16_t,
+    /// clock the divider applies to, in Hz
+    pub system_clock: uint32_t,
+    pub default_settings: uint8_t,
+    pub enabled: uint8_t,
+    pub mode: SPI_Mode,
+    /// SPI clock, in Hz
+    pub clock: uint32_t,
+    pub bit_order: uint8_t,
+    pub data_mode: uint8_t,
+    pub ss_pin: pin_t,
+}
+
+pub type HAL_SPI_DMA_UserCallback = extern "C" fn();
+/// `state` is `1` when the slave gets selected and `0` when deselected
+pub type HAL_SPI_Select_UserCallback = extern "C" fn(state: uint8_t);
+
+#[repr(C)]
+pub struct HAL_SPI_TransferStatus {
+    pub configured_transfer_length: uint32_t,
+    pub transfer_length: uint32_t,
+    /// `SPI_STATUS_*` bits
+    pub flags: uint8_t,
+}
+
+// `HAL_SPI_TransferStatus` flags
+pub const SPI_STATUS_TRANSFER_ONGOING: uint8_t = 1 << 0;
+pub const SPI_STATUS_SS_STATE: uint8_t = 1 << 1;
+
+#[repr(u32)]
+pub enum HAL_USART_Serial {
+    HAL_USART_SERIAL1 = 0,
+    HAL_USART_SERIAL2 = 1,
+}
+
+#[repr(u32)]
+pub enum HAL_USB_USART_Serial {
+    HAL_USB_USART_SERIAL = 0,
+    HAL_USB_USART_SERIAL1 = 1,
+}
+
+#[repr(C)]
+pub struct HAL_USB_USART_Config {
+    /// size of this struct
+    pub size: uint16_t,
+    pub rx_buffer: *mut uint8_t,
+    pub rx_buffer_size: uint16_t,
+    pub tx_buffer: *mut uint8_t,
+    pub tx_buffer_size: uint16_t,
+}
+
+pub const SERIAL_BUFFER_SIZE: usize = 64;
+
+/// `usart_hal.h` ring buffer; 16-bit entries so that it can hold 9-bit data
+#[repr(C)]
+pub struct Ring_Buffer {
+    pub buffer: [uint16_t; SERIAL_BUFFER_SIZE],
+    pub head: uint16_t,
+    pub tail: uint16_t,
+}
+
+const _: () =
+    assert!(mem::size_of::<Ring_Buffer>() == 2 * SERIAL_BUFFER_SIZE + 4);
+
+// `HAL_USART_BeginConfig` configuration flags
+pub const SERIAL_STOP_BITS_1: uint32_t = 0b0000_0000;
+pub const SERIAL_STOP_BITS_2: uint32_t = 0b0000_0001;
+pub const SERIAL_STOP_BITS_0_5: uint32_t = 0b0000_0010;
+pub const SERIAL_STOP_BITS_1_5: uint32_t = 0b0000_0011;
+pub const SERIAL_PARITY_NO: uint32_t = 0b0000_0000;
+pub const SERIAL_PARITY_EVEN: uint32_t = 0b0000_0100;
+pub const SERIAL_PARITY_ODD: uint32_t = 0b0000_1000;
+pub const SERIAL_DATA_BITS_8: uint32_t = 0b0000_0000;
+pub const SERIAL_DATA_BITS_9: uint32_t = 0b0001_0000;
+pub const SERIAL_DATA_BITS_7: uint32_t = 0b0010_0000;
+pub const LIN_MODE_MASTER: uint32_t = 0x0100;
+pub const LIN_MODE_SLAVE: uint32_t = 0x0200;
+pub const LIN_BREAK_13B: uint32_t = 0x0000;
+pub const LIN_BREAK_10B: uint32_t = 0x0400;
+pub const LIN_BREAK_11B: uint32_t = 0x0800;
+
 #[repr(u8)]
 pub enum Spark_Data_TypeDef {
     CLOUD_VAR_BOOLEAN = 1,
@@ -56,6 +306,78 @@ extern "C" {
     pub fn HAL_Delay_Milliseconds(ms: uint32_t);
     /// `delayMicroseconds`
     pub fn HAL_Delay_Microseconds(us: uint32_t);
+    /// `Time.now`
+    pub fn HAL_RTC_Get_UnixTime() -> time_t;
+    /// `Time.isValid`
+    pub fn HAL_RTC_Time_Is_Valid(_: *mut c_void) -> uint8_t;
+    /// Masks the interrupts; returns the previous state for `HAL_enable_irq`
+    pub fn HAL_disable_irq() -> c_int;
+    /// Restores the interrupt mask returned by `HAL_disable_irq`
+    pub fn HAL_enable_irq(state: c_int);
+
+    // hal_can
+    /// Low level version of the `CANChannel` constructor; sets the sizes of
+    /// the receive and transmit queues
+    pub fn HAL_CAN_Init(
+        channel: HAL_CAN_Channel,
+        rx_queue_size: uint16_t,
+        tx_queue_size: uint16_t,
+        _: *mut c_void,
+    );
+    /// `CANChannel.begin`
+    pub fn HAL_CAN_Begin(
+        channel: HAL_CAN_Channel,
+        baud: uint32_t,
+        flags: uint32_t,
+        _: *mut c_void,
+    );
+    /// `CANChannel.end`
+    pub fn HAL_CAN_End(channel: HAL_CAN_Channel, _: *mut c_void);
+    /// `CANChannel.transmit`; returns `false` if the transmit queue is full
+    pub fn HAL_CAN_Transmit(
+        channel: HAL_CAN_Channel,
+        message: *const CANMessage,
+        _: *mut c_void,
+    ) -> bool;
+    /// `CANChannel.receive`; returns `false` if the receive queue is empty
+    pub fn HAL_CAN_Receive(
+        channel: HAL_CAN_Channel,
+        message: *mut CANMessage,
+        _: *mut c_void,
+    ) -> bool;
+    /// `CANChannel.available`
+    pub fn HAL_CAN_Available_Messages(
+        channel: HAL_CAN_Channel,
+        _: *mut c_void,
+    ) -> uint8_t;
+    /// `CANChannel.addFilter`; returns `false` if all the filter banks are
+    /// in use
+    pub fn HAL_CAN_Add_Filter(
+        channel: HAL_CAN_Channel,
+        id: uint32_t,
+        mask: uint32_t,
+        filter_type: HAL_CAN_Filters,
+        _: *mut c_void,
+    ) -> bool;
+    /// `CANChannel.clearFilters`
+    pub fn HAL_CAN_Clear_Filters(channel: HAL_CAN_Channel, _: *mut c_void);
+    /// `CANChannel.isEnabled`
+    pub fn HAL_CAN_Is_Enabled(channel: HAL_CAN_Channel) -> bool;
+    /// `CANChannel.errorStatus`; returns one of the `CAN_*` error values
+    pub fn HAL_CAN_Error_Status(channel: HAL_CAN_Channel) -> uint32_t;
+
+    // hal_concurrent
+    /// Creates a mutex that the owning thread can lock again; returns `0`
+    /// on success
+    pub fn os_mutex_recursive_create(
+        mutex: *mut os_mutex_recursive_t,
+    ) -> c_int;
+    /// Destroys a recursive mutex
+    pub fn os_mutex_recursive_destroy(mutex: os_mutex_recursive_t) -> c_int;
+    /// Blocks until the mutex is acquired
+    pub fn os_mutex_recursive_lock(mutex: os_mutex_recursive_t) -> c_int;
+    /// Releases the mutex once
+    pub fn os_mutex_recursive_unlock(mutex: os_mutex_recursive_t) -> c_int;
 
     // hal_core
     /// Low level version of `deviceID`
@@ -72,15 +394,324 @@ extern "C" {
     /// `pinMode`
     pub fn HAL_Pin_Mode(pin: pin_t, mode: PinMode);
 
+    // hal_i2c
+    /// Low level version of the `TwoWire` constructor
+    pub fn HAL_I2C_Init(i2c: HAL_I2C_Interface, _: *mut c_void);
+    /// `Wire.setSpeed`; must be called before `HAL_I2C_Begin`
+    pub fn HAL_I2C_Set_Speed(
+        i2c: HAL_I2C_Interface,
+        speed: uint32_t,
+        _: *mut c_void,
+    );
+    /// `Wire.begin`
+    pub fn HAL_I2C_Begin(
+        i2c: HAL_I2C_Interface,
+        mode: I2C_Mode,
+        address: uint8_t,
+        _: *mut c_void,
+    );
+    /// `Wire.end`
+    pub fn HAL_I2C_End(i2c: HAL_I2C_Interface, _: *mut c_void);
+    /// `Wire.isEnabled`
+    pub fn HAL_I2C_Is_Enabled(i2c: HAL_I2C_Interface, _: *mut c_void) -> bool;
+    /// `Wire.requestFrom`; returns the number of bytes received
+    pub fn HAL_I2C_Request_Data(
+        i2c: HAL_I2C_Interface,
+        address: uint8_t,
+        quantity: uint8_t,
+        stop: uint8_t,
+        _: *mut c_void,
+    ) -> uint32_t;
+    /// `Wire.beginTransmission`
+    pub fn HAL_I2C_Begin_Transmission(
+        i2c: HAL_I2C_Interface,
+        address: uint8_t,
+        _: *mut c_void,
+    );
+    /// `Wire.endTransmission`; returns `0` on success
+    pub fn HAL_I2C_End_Transmission(
+        i2c: HAL_I2C_Interface,
+        stop: uint8_t,
+        _: *mut c_void,
+    ) -> uint8_t;
+    /// `Wire.write`; returns `0` if the transmit buffer is full
+    pub fn HAL_I2C_Write_Data(
+        i2c: HAL_I2C_Interface,
+        data: uint8_t,
+        _: *mut c_void,
+    ) -> uint32_t;
+    /// `Wire.available`
+    pub fn HAL_I2C_Available_Data(
+        i2c: HAL_I2C_Interface,
+        _: *mut c_void,
+    ) -> int32_t;
+    /// `Wire.read`
+    pub fn HAL_I2C_Read_Data(i2c: HAL_I2C_Interface, _: *mut c_void) -> int32_t;
+    /// `Wire.onReceive`; the callback gets the number of bytes received
+    pub fn HAL_I2C_Set_Callback_On_Receive(
+        i2c: HAL_I2C_Interface,
+        callback: extern "C" fn(c_int),
+        _: *mut c_void,
+    );
+    /// `Wire.onRequest`
+    pub fn HAL_I2C_Set_Callback_On_Request(
+        i2c: HAL_I2C_Interface,
+        callback: extern "C" fn(),
+        _: *mut c_void,
+    );
+
+    // hal_ota
+    /// Size of the flash region that receives firmware updates
+    pub fn HAL_OTA_FlashLength() -> uint32_t;
+
+    // hal_spi
+    /// Low level version of the `SPIClass` constructor
+    pub fn HAL_SPI_Init(spi: HAL_SPI_Interface);
+    /// `SPI.begin`
+    pub fn HAL_SPI_Begin_Ext(
+        spi: HAL_SPI_Interface,
+        mode: SPI_Mode,
+        ss_pin: pin_t,
+        _: *mut c_void,
+    );
+    /// `SPI.end`
+    pub fn HAL_SPI_End(spi: HAL_SPI_Interface);
+    /// `SPI.isEnabled`
+    pub fn HAL_SPI_Is_Enabled(spi: HAL_SPI_Interface) -> bool;
+    /// `SPI.onSelect`
+    pub fn HAL_SPI_Set_Callback_On_Select(
+        spi: HAL_SPI_Interface,
+        callback: HAL_SPI_Select_UserCallback,
+        _: *mut c_void,
+    );
+    /// `SPI.setBitOrder`
+    pub fn HAL_SPI_Set_Bit_Order(spi: HAL_SPI_Interface, order: uint8_t);
+    /// `SPI.setDataMode`
+    pub fn HAL_SPI_Set_Data_Mode(spi: HAL_SPI_Interface, mode: uint8_t);
+    /// `SPI.setClockDivider`
+    pub fn HAL_SPI_Set_Clock_Divider(spi: HAL_SPI_Interface, rate: uint8_t);
+    /// `SPI.beginTransaction`; applies all the settings at once. Returns
+    /// `0` on success
+    pub fn HAL_SPI_Set_Settings(
+        spi: HAL_SPI_Interface,
+        set_default: uint8_t,
+        clockdiv: uint8_t,
+        order: uint8_t,
+        mode: uint8_t,
+        _: *mut c_void,
+    ) -> int32_t;
+    /// `SPI.transfer`
+    pub fn HAL_SPI_Send_Receive_Data(
+        spi: HAL_SPI_Interface,
+        data: uint16_t,
+    ) -> uint16_t;
+    /// `SPI.transfer(tx_buffer, rx_buffer, length, callback)`; either buffer
+    /// can be null
+    pub fn HAL_SPI_DMA_Transfer(
+        spi: HAL_SPI_Interface,
+        tx_buffer: *mut c_void,
+        rx_buffer: *mut c_void,
+        length: uint32_t,
+        callback: HAL_SPI_DMA_UserCallback,
+    );
+    /// `SPI.available`; returns the number of bytes transferred
+    pub fn HAL_SPI_DMA_Transfer_Status(
+        spi: HAL_SPI_Interface,
+        status: *mut HAL_SPI_TransferStatus,
+    ) -> int32_t;
+    /// `SPI.transferCancel`
+    pub fn HAL_SPI_DMA_Transfer_Cancel(spi: HAL_SPI_Interface);
+    /// Current settings; used by `SPI.setClockSpeed`
+    pub fn HAL_SPI_Info(
+        spi: HAL_SPI_Interface,
+        info: *mut hal_spi_info_t,
+        _: *mut c_void,
+    );
+
     // hal_usart (old API)
     /// `Serial.begin`
     pub fn USB_USART_Init(baud_rate: uint32_t);
     /// `Serial.write`
     pub fn USB_USART_Send_Data(byte: uint8_t);
+    /// `Serial.available`
+    pub fn USB_USART_Available_Data() -> uint8_t;
+    /// `Serial.read` (`peek = 0`) and `Serial.peek` (`peek = 1`)
+    pub fn USB_USART_Receive_Data(peek: uint8_t) -> int32_t;
+    /// `Serial.baud`
+    pub fn USB_USART_Baud_Rate() -> c_uint;
+    /// `Serial.flush`
+    pub fn USB_USART_Flush_Data();
+    /// `Serial.availableForWrite`
+    pub fn USB_USART_Available_Data_For_Write() -> int32_t;
+
+    // hal_usart
+    /// Low level version of the `USARTSerial` constructor
+    pub fn HAL_USART_Init(
+        serial: HAL_USART_Serial,
+        rx_buffer: *mut Ring_Buffer,
+        tx_buffer: *mut Ring_Buffer,
+    );
+    /// `Serial1.begin`
+    pub fn HAL_USART_Begin(serial: HAL_USART_Serial, baud: uint32_t);
+    /// `Serial1.begin` with a configuration argument
+    pub fn HAL_USART_BeginConfig(
+        serial: HAL_USART_Serial,
+        baud: uint32_t,
+        config: uint32_t,
+        _: *mut c_void,
+    );
+    /// `Serial1.end`
+    pub fn HAL_USART_End(serial: HAL_USART_Serial);
+    /// `Serial1.write`
+    pub fn HAL_USART_Write_Data(
+        serial: HAL_USART_Serial,
+        data: uint8_t,
+    ) -> uint32_t;
+    /// `Serial1.available`
+    pub fn HAL_USART_Available_Data(serial: HAL_USART_Serial) -> int32_t;
+    /// `Serial1.availableForWrite`
+    pub fn HAL_USART_Available_Data_For_Write(
+        serial: HAL_USART_Serial,
+    ) -> int32_t;
+    /// `Serial1.read`
+    pub fn HAL_USART_Read_Data(serial: HAL_USART_Serial) -> int32_t;
+    /// `Serial1.peek`
+    pub fn HAL_USART_Peek_Data(serial: HAL_USART_Serial) -> int32_t;
+    /// `Serial1.flush`
+    pub fn HAL_USART_Flush_Data(serial: HAL_USART_Serial);
+    /// `Serial1.isEnabled`
+    pub fn HAL_USART_Is_Enabled(serial: HAL_USART_Serial) -> bool;
+    /// `Serial1.halfduplex`
+    pub fn HAL_USART_Half_Duplex(serial: HAL_USART_Serial, enable: bool);
+    /// `Serial1.write` in 9-bit mode
+    pub fn HAL_USART_Write_NineBitData(
+        serial: HAL_USART_Serial,
+        data: uint16_t,
+    ) -> uint32_t;
+    /// `Serial1.sendBreak`
+    pub fn HAL_USART_Send_Break(serial: HAL_USART_Serial, _: *mut c_void);
+    /// `Serial1.breakRx`
+    pub fn HAL_USART_Break_Detected(serial: HAL_USART_Serial) -> uint8_t;
+
+    // hal_usb
+    /// Low level version of the `USBSerial` constructor
+    pub fn HAL_USB_USART_Init(
+        serial: HAL_USB_USART_Serial,
+        config: *const HAL_USB_USART_Config,
+    );
+    /// `Serial.begin`
+    pub fn HAL_USB_USART_Begin(
+        serial: HAL_USB_USART_Serial,
+        baud: uint32_t,
+        _: *mut c_void,
+    );
+    /// `Serial.end`
+    pub fn HAL_USB_USART_End(serial: HAL_USB_USART_Serial);
+    /// `Serial.baud`
+    pub fn HAL_USB_USART_Baud_Rate(serial: HAL_USB_USART_Serial) -> c_uint;
+    /// `Serial.available`
+    pub fn HAL_USB_USART_Available_Data(
+        serial: HAL_USB_USART_Serial,
+    ) -> int32_t;
+    /// `Serial.availableForWrite`
+    pub fn HAL_USB_USART_Available_Data_For_Write(
+        serial: HAL_USB_USART_Serial,
+    ) -> int32_t;
+    /// `Serial.read` (`peek = 0`) and `Serial.peek` (`peek = 1`)
+    pub fn HAL_USB_USART_Receive_Data(
+        serial: HAL_USB_USART_Serial,
+        peek: uint8_t,
+    ) -> int32_t;
+    /// `Serial.write`
+    pub fn HAL_USB_USART_Send_Data(
+        serial: HAL_USB_USART_Serial,
+        data: uint8_t,
+    ) -> int32_t;
+    /// `Serial.flush`
+    pub fn HAL_USB_USART_Flush_Data(serial: HAL_USB_USART_Serial);
+    /// `Serial.isEnabled`
+    pub fn HAL_USB_USART_Is_Enabled(serial: HAL_USB_USART_Serial) -> bool;
+    /// `Serial.isConnected`
+    pub fn HAL_USB_USART_Is_Connected(serial: HAL_USB_USART_Serial) -> bool;
+    /// Low level version of the `USBKeyboard`/`USBMouse` constructors
+    pub fn HAL_USB_HID_Init(reserved: uint8_t, _: *mut c_void);
+    /// `Keyboard.begin`/`Mouse.begin`
+    pub fn HAL_USB_HID_Begin(reserved: uint8_t, _: *mut c_void);
+    /// Sends a report; the first byte is the report ID
+    pub fn HAL_USB_HID_Send_Report(
+        reserved: uint8_t,
+        report: *mut c_void,
+        len: uint16_t,
+        _: *mut c_void,
+    );
+    /// `Keyboard.end`/`Mouse.end`
+    pub fn HAL_USB_HID_End(reserved: uint8_t);
+    /// Non-zero while the last report is still being sent
+    pub fn HAL_USB_HID_Status(reserved: uint8_t, _: *mut c_void) -> int32_t;
+    /// Initializes the USB device peripheral
+    pub fn HAL_USB_Init();
+    /// Connects to the host
+    pub fn HAL_USB_Attach();
+    /// Disconnects from the host
+    pub fn HAL_USB_Detach();
+    /// Installs the handler of the vendor specific control requests; `p` is
+    /// passed back to `cb`
+    pub fn HAL_USB_Set_Vendor_Request_Callback(
+        cb: HAL_USB_Vendor_Request_Callback,
+        p: *mut c_void,
+    );
 
     // system
     /// `delay`
     pub fn system_delay_ms(ms: c_ulong, force_no_background_loop: bool);
+    /// `System.on`
+    pub fn system_subscribe_event(
+        events: system_event_t,
+        handler: system_event_handler_t,
+        _: *mut c_void,
+    ) -> c_int;
+    /// Whether the application runs in its own thread
+    /// (`SYSTEM_THREAD(ENABLED)`); see `FEATURE_ENABLED`
+    pub fn system_thread_get_state(_: *mut c_void) -> c_int;
+    /// Installs the handler that runs when a firmware update is requested
+    /// over serial, in listening mode
+    pub fn set_ymodem_serial_flash_update_handler(
+        handler: ymodem_serial_flash_update_handler,
+    );
+    /// `System.firmwareUpdate`; YMODEM receiver of the system firmware
+    pub fn system_firmwareUpdate(serial: *mut Stream, _: *mut c_void) -> bool;
+    /// Starts a firmware update; returns `0` on success
+    pub fn Spark_Prepare_For_Firmware_Update(
+        file: &mut FileTransfer_Descriptor,
+        flags: uint32_t,
+        _: *mut c_void,
+    ) -> c_int;
+    /// Writes `file.chunk_size` bytes at `file.chunk_address`; returns `0`
+    /// on success
+    pub fn Spark_Save_Firmware_Chunk(
+        file: &mut FileTransfer_Descriptor,
+        chunk: *const uint8_t,
+        _: *mut c_void,
+    ) -> c_int;
+    /// Completes (`UPDATE_FLAG_SUCCESS`) or aborts (`0`) a firmware update;
+    /// returns `0` on success
+    pub fn Spark_Finish_Firmware_Update(
+        file: &mut FileTransfer_Descriptor,
+        flags: uint32_t,
+        _: *mut c_void,
+    ) -> c_int;
+    /// Installs the handler of the `USB_REQUEST_CUSTOM` requests
+    /// (`usb_request_custom_handler`)
+    pub fn system_set_usb_request_app_handler(
+        handler: usb_request_app_handler_type,
+        _: *mut c_void,
+    );
+    /// Completes a request accepted by the app handler; `0` means success
+    pub fn system_set_usb_request_result(
+        req: *mut USBRequest,
+        result: c_int,
+        _: *mut c_void,
+    );
 
     // system_cloud
     /// `Particle.function`
@@ -96,10 +727,21 @@ extern "C" {
         ty: Spark_Data_TypeDef,
         _: *mut spark_variable_t,
     ) -> bool;
+    /// `Particle.syncTime`
+    pub fn spark_sync_time(_: *mut c_void) -> bool;
+    /// `Particle.syncTimePending`
+    pub fn spark_sync_time_pending(_: *mut c_void) -> bool;
+    /// `Particle.timeSyncedLast`
+    pub fn spark_sync_time_last(
+        tm: *mut time_t,
+        _: *mut c_void,
+    ) -> system_tick_t;
     /// `deviceID`
     pub fn spark_deviceID() -> String;
     /// `micros`
     pub fn HAL_Timer_Get_Micro_Seconds() -> system_tick_t;
+    /// `millis`
+    pub fn HAL_Timer_Get_Milli_Seconds() -> system_tick_t;
 }
 
 // TODO add bindings for all functions below, but be sure to know which
@@ -160,16 +802,6 @@ extern "C" {
 // DYNALIB_FN(BASE_IDX2 + 2, communication, spark_protocol_time_request_pending, bool(ProtocolFacade*, void*))
 // DYNALIB_FN(BASE_IDX2 + 3, communication, spark_protocol_time_last_synced, system_tick_t(ProtocolFacade*, time_t*, void*))
 // DYNALIB_FN(0, hal_bootloader, HAL_Bootloader_Image, const uint8_t*(uint32_t*, void*))
-// DYNALIB_FN(0, hal_can, HAL_CAN_Init, void(HAL_CAN_Channel, uint16_t, uint16_t, void*))
-// DYNALIB_FN(1, hal_can, HAL_CAN_Begin, void(HAL_CAN_Channel, uint32_t, uint32_t, void*))
-// DYNALIB_FN(2, hal_can, HAL_CAN_End, void(HAL_CAN_Channel, void*))
-// DYNALIB_FN(3, hal_can, HAL_CAN_Transmit, bool(HAL_CAN_Channel, const CANMessage*, void*))
-// DYNALIB_FN(4, hal_can, HAL_CAN_Receive, bool(HAL_CAN_Channel, CANMessage*, void*))
-// DYNALIB_FN(5, hal_can, HAL_CAN_Available_Messages, uint8_t(HAL_CAN_Channel, void*))
-// DYNALIB_FN(6, hal_can, HAL_CAN_Add_Filter, bool(HAL_CAN_Channel, uint32_t, uint32_t, HAL_CAN_Filters, void*))
-// DYNALIB_FN(7, hal_can, HAL_CAN_Clear_Filters, void(HAL_CAN_Channel, void*))
-// DYNALIB_FN(8, hal_can, HAL_CAN_Is_Enabled, bool(HAL_CAN_Channel))
-// DYNALIB_FN(9, hal_can, HAL_CAN_Error_Status, HAL_CAN_Errors(HAL_CAN_Channel))
 // DYNALIB_FN(0, hal_cellular, cellular_off, cellular_result_t(void*))
 // DYNALIB_FN(1, hal_cellular, cellular_on, cellular_result_t(void*))
 // DYNALIB_FN(2, hal_cellular, cellular_init, cellular_result_t(void*))
@@ -218,11 +850,7 @@ extern "C" {
 // DYNALIB_FN(14, hal_concurrent, os_mutex_lock, int(os_mutex_t))
 // DYNALIB_FN(15, hal_concurrent, os_mutex_trylock, int(os_mutex_t))
 // DYNALIB_FN(16, hal_concurrent, os_mutex_unlock, int(os_mutex_t))
-// DYNALIB_FN(17, hal_concurrent, os_mutex_recursive_create, int(os_mutex_recursive_t*))
-// DYNALIB_FN(18, hal_concurrent, os_mutex_recursive_destroy, int(os_mutex_recursive_t))
-// DYNALIB_FN(19, hal_concurrent, os_mutex_recursive_lock, int(os_mutex_recursive_t))
 // DYNALIB_FN(20, hal_concurrent, os_mutex_recursive_trylock, int(os_mutex_recursive_t))
-// DYNALIB_FN(21, hal_concurrent, os_mutex_recursive_unlock, int(os_mutex_recursive_t))
 // DYNALIB_FN(22, hal_concurrent, os_timer_is_active, int(os_timer_t, void*))
 // DYNALIB_FN(23, hal_concurrent, os_queue_create, int(os_queue_t*, size_t, size_t, void*))
 // DYNALIB_FN(24, hal_concurrent, os_queue_destroy, int(os_queue_t, void*))
@@ -296,24 +924,19 @@ extern "C" {
 // DYNALIB_FN(0, hal, HAL_RNG_Configuration, void(void))
 // DYNALIB_FN(1, hal, HAL_RNG_GetRandomNumber, uint32_t(void))
 // DYNALIB_FN(BASE_IDX + 2, hal, HAL_Timer_Get_Micro_Seconds, system_tick_t(void))
-// DYNALIB_FN(BASE_IDX + 3, hal, HAL_Timer_Get_Milli_Seconds, system_tick_t(void))
 // DYNALIB_FN(BASE_IDX + 4, hal, HAL_RTC_Configuration, void(void))
-// DYNALIB_FN(BASE_IDX + 5, hal, HAL_RTC_Get_UnixTime, time_t(void))
 // DYNALIB_FN(BASE_IDX + 6, hal, HAL_RTC_Set_UnixTime, void(time_t))
 // DYNALIB_FN(BASE_IDX + 7, hal, HAL_RTC_Set_UnixAlarm, void(time_t))
 // DYNALIB_FN(BASE_IDX + 8, hal, HAL_EEPROM_Init, void(void))
 // DYNALIB_FN(BASE_IDX + 9, hal, HAL_EEPROM_Read, uint8_t(uint32_t))
 // DYNALIB_FN(BASE_IDX + 10, hal, HAL_EEPROM_Write, void(uint32_t, uint8_t))
 // DYNALIB_FN(BASE_IDX + 11, hal, HAL_EEPROM_Length, size_t(void))
-// DYNALIB_FN(BASE_IDX + 12, hal, HAL_disable_irq, int(void))
-// DYNALIB_FN(BASE_IDX + 13, hal, HAL_enable_irq, void(int))
 // DYNALIB_FN(BASE_IDX + 14, hal, HAL_RTC_Cancel_UnixAlarm, void(void))
 // DYNALIB_FN(BASE_IDX + 15, hal,HAL_EEPROM_Get, void(uint32_t, void *, size_t))
 // DYNALIB_FN(BASE_IDX + 16, hal,HAL_EEPROM_Put, void(uint32_t, const void *, size_t))
 // DYNALIB_FN(BASE_IDX + 17, hal,HAL_EEPROM_Clear, void(void))
 // DYNALIB_FN(BASE_IDX + 18, hal,HAL_EEPROM_Has_Pending_Erase, bool(void))
 // DYNALIB_FN(BASE_IDX + 19, hal,HAL_EEPROM_Perform_Pending_Erase, void(void))
-// DYNALIB_FN(BASE_IDX + 20, hal, HAL_RTC_Time_Is_Valid, uint8_t(void*))
 // DYNALIB_FN(0, hal_i2c, HAL_I2C_Set_Speed_v1, void(uint32_t))
 // DYNALIB_FN(1, hal_i2c, HAL_I2C_Enable_DMA_Mode_v1, void(bool))
 // DYNALIB_FN(2, hal_i2c, HAL_I2C_Stretch_Clock_v1, void(bool))
@@ -330,25 +953,11 @@ extern "C" {
 // DYNALIB_FN(13, hal_i2c, HAL_I2C_Is_Enabled_v1, bool(void))
 // DYNALIB_FN(14, hal_i2c, HAL_I2C_Set_Callback_On_Receive_v1, void(void(*)(int)))
 // DYNALIB_FN(15, hal_i2c, HAL_I2C_Set_Callback_On_Request_v1, void(void(*)(void)))
-// DYNALIB_FN(BASE_IDX + 0, hal_i2c, HAL_I2C_Set_Speed, void(HAL_I2C_Interface, uint32_t, void*))
 // DYNALIB_FN(BASE_IDX + 1, hal_i2c, HAL_I2C_Enable_DMA_Mode, void(HAL_I2C_Interface, bool, void*))
 // DYNALIB_FN(BASE_IDX + 2, hal_i2c, HAL_I2C_Stretch_Clock, void(HAL_I2C_Interface, bool, void*))
-// DYNALIB_FN(BASE_IDX + 3, hal_i2c, HAL_I2C_Begin, void(HAL_I2C_Interface, I2C_Mode, uint8_t, void*))
-// DYNALIB_FN(BASE_IDX + 4, hal_i2c, HAL_I2C_End, void(HAL_I2C_Interface, void*))
-// DYNALIB_FN(BASE_IDX + 5, hal_i2c, HAL_I2C_Request_Data, uint32_t(HAL_I2C_Interface, uint8_t, uint8_t, uint8_t, void*))
-// DYNALIB_FN(BASE_IDX + 6, hal_i2c, HAL_I2C_Begin_Transmission, void(HAL_I2C_Interface, uint8_t, void*))
-// DYNALIB_FN(BASE_IDX + 7, hal_i2c, HAL_I2C_End_Transmission, uint8_t(HAL_I2C_Interface, uint8_t, void*))
-// DYNALIB_FN(BASE_IDX + 8, hal_i2c, HAL_I2C_Write_Data, uint32_t(HAL_I2C_Interface, uint8_t, void*))
-// DYNALIB_FN(BASE_IDX + 9, hal_i2c, HAL_I2C_Available_Data, int32_t(HAL_I2C_Interface, void*))
-// DYNALIB_FN(BASE_IDX + 10, hal_i2c, HAL_I2C_Read_Data, int32_t(HAL_I2C_Interface, void*))
 // DYNALIB_FN(BASE_IDX + 11, hal_i2c, HAL_I2C_Peek_Data, int32_t(HAL_I2C_Interface, void*))
 // DYNALIB_FN(BASE_IDX + 12, hal_i2c, HAL_I2C_Flush_Data, void(HAL_I2C_Interface, void*))
-// DYNALIB_FN(BASE_IDX + 13, hal_i2c, HAL_I2C_Is_Enabled, bool(HAL_I2C_Interface, void*))
-// DYNALIB_FN(BASE_IDX + 14, hal_i2c, HAL_I2C_Set_Callback_On_Receive, void(HAL_I2C_Interface, void(*)(int), void*))
-// DYNALIB_FN(BASE_IDX + 15, hal_i2c, HAL_I2C_Set_Callback_On_Request, void(HAL_I2C_Interface, void(*)(void), void*))
-// DYNALIB_FN(BASE_IDX + 16, hal_i2c, HAL_I2C_Init, void(HAL_I2C_Interface, void*))
 // DYNALIB_FN(0, hal_ota, HAL_OTA_FlashAddress, uint32_t(void))
-// DYNALIB_FN(1, hal_ota, HAL_OTA_FlashLength, uint32_t(void))
 // DYNALIB_FN(2, hal_ota, HAL_OTA_ChunkSize, uint16_t(void))
 // DYNALIB_FN(3, hal_ota, HAL_OTA_Flashed_GetStatus, bool(void))
 // DYNALIB_FN(4, hal_ota, HAL_OTA_Flashed_ResetStatus, void(void))
@@ -386,63 +995,9 @@ extern "C" {
 // DYNALIB_FN(14, hal_socket, socket_leave_multicast, sock_result_t(const HAL_IPAddress*, network_interface_t, socket_multicast_info_t*))
 // DYNALIB_FN(15, hal_socket, socket_peer, sock_result_t(sock_handle_t, sock_peer_t*, void*))
 // DYNALIB_FN(0, hal_spi, HAL_SPI_Begin, void(HAL_SPI_Interface, uint16_t))
-// DYNALIB_FN(1, hal_spi, HAL_SPI_End, void(HAL_SPI_Interface))
-// DYNALIB_FN(2, hal_spi, HAL_SPI_Set_Bit_Order, void(HAL_SPI_Interface, uint8_t))
-// DYNALIB_FN(3, hal_spi, HAL_SPI_Set_Data_Mode, void(HAL_SPI_Interface, uint8_t))
-// DYNALIB_FN(4, hal_spi, HAL_SPI_Set_Clock_Divider, void(HAL_SPI_Interface, uint8_t))
-// DYNALIB_FN(5, hal_spi, HAL_SPI_Send_Receive_Data, uint16_t(HAL_SPI_Interface, uint16_t))
 // DYNALIB_FN(6, hal_spi, HAL_SPI_Is_Enabled_Old, bool(void))
-// DYNALIB_FN(7, hal_spi, HAL_SPI_Init, void(HAL_SPI_Interface))
-// DYNALIB_FN(8, hal_spi, HAL_SPI_Is_Enabled, bool(HAL_SPI_Interface))
-// DYNALIB_FN(9, hal_spi, HAL_SPI_Info, void(HAL_SPI_Interface, hal_spi_info_t*, void*))
-// DYNALIB_FN(10, hal_spi, HAL_SPI_DMA_Transfer, void(HAL_SPI_Interface, void*, void*, uint32_t, HAL_SPI_DMA_UserCallback))
-// DYNALIB_FN(11, hal_spi, HAL_SPI_Begin_Ext, void(HAL_SPI_Interface, SPI_Mode, uint16_t, void*))
-// DYNALIB_FN(12, hal_spi, HAL_SPI_Set_Callback_On_Select, void(HAL_SPI_Interface, HAL_SPI_Select_UserCallback, void*))
-// DYNALIB_FN(13, hal_spi, HAL_SPI_DMA_Transfer_Cancel, void(HAL_SPI_Interface))
-// DYNALIB_FN(14, hal_spi, HAL_SPI_DMA_Transfer_Status, int32_t(HAL_SPI_Interface, HAL_SPI_TransferStatus*))
-// DYNALIB_FN(15, hal_spi, HAL_SPI_Set_Settings, int32_t(HAL_SPI_Interface, uint8_t, uint8_t, uint8_t, uint8_t, void*))
-// DYNALIB_FN(1, hal_usart, USB_USART_Available_Data, uint8_t(void))
-// DYNALIB_FN(2, hal_usart, USB_USART_Receive_Data, int32_t(uint8_t))
-// DYNALIB_FN(4, hal_usart, USB_USART_Baud_Rate, unsigned(void))
 // DYNALIB_FN(5, hal_usart, USB_USART_LineCoding_BitRate_Handler, void(void(*)(uint32_t)))
-// DYNALIB_FN(BASE_IDX + 0, hal_usart, HAL_USART_Init, void(HAL_USART_Serial, Ring_Buffer*, Ring_Buffer*))
-// DYNALIB_FN(BASE_IDX + 1, hal_usart, HAL_USART_Begin, void(HAL_USART_Serial, uint32_t))
-// DYNALIB_FN(BASE_IDX + 2, hal_usart, HAL_USART_End, void(HAL_USART_Serial))
-// DYNALIB_FN(BASE_IDX + 3, hal_usart, HAL_USART_Write_Data, uint32_t(HAL_USART_Serial, uint8_t))
-// DYNALIB_FN(BASE_IDX + 4, hal_usart, HAL_USART_Available_Data, int32_t(HAL_USART_Serial))
-// DYNALIB_FN(BASE_IDX + 5, hal_usart, HAL_USART_Read_Data, int32_t(HAL_USART_Serial))
-// DYNALIB_FN(BASE_IDX + 6, hal_usart, HAL_USART_Peek_Data, int32_t(HAL_USART_Serial))
-// DYNALIB_FN(BASE_IDX + 7, hal_usart, HAL_USART_Flush_Data, void(HAL_USART_Serial))
-// DYNALIB_FN(BASE_IDX + 8, hal_usart, HAL_USART_Is_Enabled, bool(HAL_USART_Serial))
-// DYNALIB_FN(BASE_IDX + 9, hal_usart, HAL_USART_Half_Duplex, void(HAL_USART_Serial, bool))
-// DYNALIB_FN(BASE_IDX + 10, hal_usart, HAL_USART_Available_Data_For_Write, int32_t(HAL_USART_Serial))
-// DYNALIB_FN(BASE_IDX + 11, hal_usart, USB_USART_Available_Data_For_Write, int32_t(void))
-// DYNALIB_FN(BASE_IDX + 12, hal_usart, USB_USART_Flush_Data, void(void))
-// DYNALIB_FN(BASE_IDX2 + 0, hal_usart, HAL_USART_BeginConfig, void(HAL_USART_Serial serial, uint32_t baud, uint32_t config, void *ptr))
-// DYNALIB_FN(BASE_IDX2 + 1, hal_usart, HAL_USART_Write_NineBitData, uint32_t(HAL_USART_Serial serial, uint16_t data))
-// DYNALIB_FN(BASE_IDX2 + 2, hal_usart, HAL_USART_Send_Break, void(HAL_USART_Serial, void*))
-// DYNALIB_FN(BASE_IDX2 + 3, hal_usart, HAL_USART_Break_Detected, uint8_t(HAL_USART_Serial))
-// DYNALIB_FN(0, hal_usb, HAL_USB_USART_Init, void(HAL_USB_USART_Serial, const HAL_USB_USART_Config*))
-// DYNALIB_FN(1, hal_usb, HAL_USB_USART_Begin, void(HAL_USB_USART_Serial, uint32_t, void *))
-// DYNALIB_FN(2, hal_usb, HAL_USB_USART_End, void(HAL_USB_USART_Serial))
-// DYNALIB_FN(3, hal_usb, HAL_USB_USART_Baud_Rate, unsigned int(HAL_USB_USART_Serial))
-// DYNALIB_FN(4, hal_usb, HAL_USB_USART_Available_Data, int32_t(HAL_USB_USART_Serial))
-// DYNALIB_FN(5, hal_usb, HAL_USB_USART_Available_Data_For_Write, int32_t(HAL_USB_USART_Serial))
-// DYNALIB_FN(6, hal_usb, HAL_USB_USART_Receive_Data, int32_t(HAL_USB_USART_Serial, uint8_t))
-// DYNALIB_FN(7, hal_usb, HAL_USB_USART_Send_Data, int32_t(HAL_USB_USART_Serial, uint8_t))
-// DYNALIB_FN(8, hal_usb, HAL_USB_USART_Flush_Data, void(HAL_USB_USART_Serial))
-// DYNALIB_FN(9, hal_usb, HAL_USB_USART_Is_Enabled, bool(HAL_USB_USART_Serial))
-// DYNALIB_FN(10, hal_usb, HAL_USB_USART_Is_Connected, bool(HAL_USB_USART_Serial))
 // DYNALIB_FN(11, hal_usb, HAL_USB_USART_LineCoding_BitRate_Handler, int32_t(void (*handler)(uint32_t bitRate), void* reserved))
-// DYNALIB_FN(BASE_IDX + 0, hal_usb, HAL_USB_HID_Init, void(uint8_t, void*))
-// DYNALIB_FN(BASE_IDX + 1, hal_usb, HAL_USB_HID_Begin, void(uint8_t, void*))
-// DYNALIB_FN(BASE_IDX + 2, hal_usb, HAL_USB_HID_Send_Report, void(uint8_t, void*, uint16_t, void*))
-// DYNALIB_FN(BASE_IDX + 3, hal_usb, HAL_USB_HID_End, void(uint8_t))
-// DYNALIB_FN(BASE_IDX1 + 0, hal_usb, HAL_USB_Init, void(void))
-// DYNALIB_FN(BASE_IDX1 + 1, hal_usb, HAL_USB_Attach, void(void))
-// DYNALIB_FN(BASE_IDX1 + 2, hal_usb, HAL_USB_Detach, void(void))
-// DYNALIB_FN(BASE_IDX2 + 0, hal_usb, HAL_USB_Set_Vendor_Request_Callback, void(HAL_USB_Vendor_Request_Callback, void*))
-// DYNALIB_FN(BASE_IDX4 + 0, hal_usb, HAL_USB_HID_Status, int32_t(uint8_t, void*))
 // DYNALIB_FN(BASE_IDX4 + 1, hal_usb, HAL_USB_HID_Set_State, uint8_t(uint8_t, uint8_t, void*))
 // DYNALIB_FN(0, hal_wlan, wlan_connect_init, wlan_result_t(void))
 // DYNALIB_FN(1, hal_wlan, wlan_connect_finalize, wlan_result_t(void))
@@ -540,18 +1095,12 @@ extern "C" {
 // DYNALIB_FN(8, system_cloud, spark_send_event, bool(const char*, const char*, int, uint32_t, void*))
 // DYNALIB_FN(9, system_cloud, spark_subscribe, bool(const char*, EventHandler, void*, Spark_Subscription_Scope_TypeDef, const char*, void*))
 // DYNALIB_FN(10, system_cloud, spark_unsubscribe, void(void*))
-// DYNALIB_FN(11, system_cloud, spark_sync_time, bool(void*))
-// DYNALIB_FN(12, system_cloud, spark_sync_time_pending, bool(void*))
-// DYNALIB_FN(13, system_cloud, spark_sync_time_last, system_tick_t(time_t*, void*))
 // DYNALIB_FN(14, system_cloud, spark_set_connection_property, int(unsigned, unsigned, void*, void*))
 // DYNALIB_FN(0, system, system_mode, System_Mode_TypeDef(void))
 // DYNALIB_FN(1, system, set_system_mode, void(System_Mode_TypeDef))
-// DYNALIB_FN(2, system, set_ymodem_serial_flash_update_handler, void(ymodem_serial_flash_update_handler))
-// DYNALIB_FN(3, system, system_firmwareUpdate, bool(Stream*, void*))
 // DYNALIB_FN(4, system, system_fileTransfer, bool(system_file_transfer_t*, void*))
 // DYNALIB_FN(6, system, system_sleep, void(Spark_Sleep_TypeDef, long, uint32_t, void*))
 // DYNALIB_FN(7, system, system_sleep_pin, void(uint16_t, uint16_t, long, uint32_t, void*))
-// DYNALIB_FN(8, system, system_subscribe_event, int(system_event_t, system_event_handler_t*, void*))
 // DYNALIB_FN(9, system, system_unsubscribe_event, void(system_event_t, system_event_handler_t*, void*))
 // DYNALIB_FN(10, system, system_button_pushed_duration, uint16_t(uint8_t, void*))
 // DYNALIB_FN(11, system, system_thread_set_state, void(spark::feature::State, void*))
@@ -559,17 +1108,11 @@ extern "C" {
 // DYNALIB_FN(13, system, system_internal, void*(int item, void*))
 // DYNALIB_FN(14, system, system_set_flag, int(system_flag_t, uint8_t, void*))
 // DYNALIB_FN(15, system, system_get_flag, int(system_flag_t, uint8_t*, void*))
-// DYNALIB_FN(16, system, Spark_Prepare_For_Firmware_Update, int(FileTransfer::Descriptor&, uint32_t, void*))
-// DYNALIB_FN(17, system, Spark_Save_Firmware_Chunk, int(FileTransfer::Descriptor&, const uint8_t*, void*))
-// DYNALIB_FN(18, system, Spark_Finish_Firmware_Update, int(FileTransfer::Descriptor&, uint32_t, void*))
 // DYNALIB_FN(19, system, application_thread_current, uint8_t(void*))
 // DYNALIB_FN(20, system, system_thread_current, uint8_t(void*))
 // DYNALIB_FN(21, system, application_thread_invoke, uint8_t(void(*)(void*), void*, void*))
-// DYNALIB_FN(22, system, system_thread_get_state, spark::feature::State(void*))
 // DYNALIB_FN(23, system, system_notify_time_changed, void(uint32_t, void*, void*))
 // DYNALIB_FN(24, system, main_thread_current, uint8_t(void*))
-// DYNALIB_FN(25, system, system_set_usb_request_app_handler, void(usb_request_app_handler_type, void*))
-// DYNALIB_FN(26, system, system_set_usb_request_result, void(USBRequest*, int, void*))
 // DYNALIB_FN(BASE_IDX + 0, system, led_start_signal, int(int, uint8_t, int, void*))
 // DYNALIB_FN(BASE_IDX + 1, system, led_stop_signal, void(int, int, void*))
 // DYNALIB_FN(BASE_IDX + 2, system, led_signal_started, int(int, void*))
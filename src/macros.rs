@@ -0,0 +1,25 @@
+/// Prints to the USB serial port
+///
+/// Equivalent to `print!` but for `UsbSerial`
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => {
+        $crate::__serial_print(format_args!($($arg)*))
+    }
+}
+
+/// Prints to the USB serial port, with a newline
+///
+/// The line is terminated with `\r\n`
+#[macro_export]
+macro_rules! serial_println {
+    () => {
+        $crate::serial_print!("\r\n")
+    };
+    ($fmt:expr) => {
+        $crate::serial_print!(concat!($fmt, "\r\n"))
+    };
+    ($fmt:expr, $($arg:tt)*) => {
+        $crate::serial_print!(concat!($fmt, "\r\n"), $($arg)*)
+    };
+}
@@ -0,0 +1,100 @@
+//! Host implementations of the HAL functions used by the unit tests
+
+use std::collections::VecDeque;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+use std::vec::Vec;
+
+use cty::c_int;
+use ll::{HAL_USART_Serial, c_void};
+
+/// Serializes the tests that use the USART mock
+static USART: Mutex<()> = Mutex::new(());
+
+/// Words waiting to be read; shared by all the ports
+static USART_RX: Mutex<VecDeque<u16>> = Mutex::new(VecDeque::new());
+
+/// Makes `words` the only data available to the USARTs
+///
+/// The USARTs keep reading these words until the returned guard is dropped
+pub fn usart_receive(words: &[u16]) -> MutexGuard<'static, ()> {
+    let guard = USART.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut rx = USART_RX.lock().unwrap();
+    rx.clear();
+    rx.extend(words);
+
+    guard
+}
+
+#[no_mangle]
+extern "C" fn HAL_USART_Is_Enabled(_: HAL_USART_Serial) -> bool {
+    true
+}
+
+#[no_mangle]
+extern "C" fn HAL_USART_Available_Data(_: HAL_USART_Serial) -> i32 {
+    USART_RX.lock().unwrap().len() as i32
+}
+
+#[no_mangle]
+extern "C" fn HAL_USART_Read_Data(_: HAL_USART_Serial) -> i32 {
+    USART_RX.lock().unwrap().pop_front().map_or(-1, i32::from)
+}
+
+#[no_mangle]
+extern "C" fn HAL_USART_Peek_Data(_: HAL_USART_Serial) -> i32 {
+    USART_RX.lock().unwrap().front().map_or(-1, |&word| i32::from(word))
+}
+
+/// Serializes the tests that use the HID mock
+static HID: Mutex<()> = Mutex::new(());
+
+/// HID reports sent so far
+static HID_REPORTS: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+/// Clears the list of sent HID reports
+///
+/// The reports sent until the returned guard is dropped are recorded and
+/// returned by `hid_reports`
+pub fn hid_capture() -> MutexGuard<'static, ()> {
+    let guard = HID.lock().unwrap_or_else(|e| e.into_inner());
+    HID_REPORTS.lock().unwrap().clear();
+    guard
+}
+
+/// Returns the HID reports sent since `hid_capture`
+pub fn hid_reports() -> Vec<Vec<u8>> {
+    HID_REPORTS.lock().unwrap().clone()
+}
+
+#[no_mangle]
+extern "C" fn HAL_USB_HID_Status(_: u8, _: *mut c_void) -> i32 {
+    0
+}
+
+#[no_mangle]
+extern "C" fn HAL_USB_HID_Send_Report(
+    _: u8,
+    report: *mut c_void,
+    len: u16,
+    _: *mut c_void,
+) {
+    let report =
+        unsafe { slice::from_raw_parts(report as *const u8, len.into()) };
+    HID_REPORTS.lock().unwrap().push(report.to_vec());
+}
+
+#[no_mangle]
+extern "C" fn HAL_Timer_Get_Milli_Seconds() -> u32 {
+    0
+}
+
+// the host has no interrupts to mask
+#[no_mangle]
+extern "C" fn HAL_disable_irq() -> c_int {
+    0
+}
+
+#[no_mangle]
+extern "C" fn HAL_enable_irq(_: c_int) {}
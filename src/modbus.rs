@@ -0,0 +1,1090 @@
+//! Modbus RTU master and slave
+//!
+//! Both roles work on top of any serial port that implements the
+//! `embedded-hal` serial traits, like `Serial1`. An optional output pin
+//! drives the direction (DE/RE) input of an RS-485 transceiver: it's set high
+//! while transmitting and low otherwise.
+
+use core::convert::Infallible;
+
+use hal::digital::v2::OutputPin;
+use hal::serial::{Read, Write};
+use nb;
+
+/// Maximum size of an RTU frame
+pub const MAX_FRAME_SIZE: usize = 256;
+
+/// Broadcast slave address; slaves don't reply to broadcast requests
+pub const BROADCAST: u8 = 0;
+
+const READ_COILS: u8 = 0x01;
+const READ_DISCRETE_INPUTS: u8 = 0x02;
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const WRITE_SINGLE_COIL: u8 = 0x05;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+const WRITE_MULTIPLE_COILS: u8 = 0x0F;
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+/// Computes the Modbus CRC16 of `data`
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF;
+    for &byte in data {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// Exception codes
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Exception {
+    IllegalFunction,
+    IllegalDataAddress,
+    IllegalDataValue,
+    ServerDeviceFailure,
+    /// Any other exception code
+    Other(u8),
+}
+
+impl Exception {
+    fn from_code(code: u8) -> Self {
+        match code {
+            1 => Exception::IllegalFunction,
+            2 => Exception::IllegalDataAddress,
+            3 => Exception::IllegalDataValue,
+            4 => Exception::ServerDeviceFailure,
+            code => Exception::Other(code),
+        }
+    }
+
+    fn code(&self) -> u8 {
+        match *self {
+            Exception::IllegalFunction => 1,
+            Exception::IllegalDataAddress => 2,
+            Exception::IllegalDataValue => 3,
+            Exception::ServerDeviceFailure => 4,
+            Exception::Other(code) => code,
+        }
+    }
+}
+
+/// Master errors
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The slave didn't reply in time
+    Timeout,
+    /// The reply had a wrong CRC
+    Crc,
+    /// The reply doesn't match the request
+    InvalidResponse,
+    /// The request doesn't fit in a frame
+    InvalidRequest,
+    /// The slave replied with an exception
+    Exception(Exception),
+    /// The serial port reported an error
+    Serial,
+}
+
+/// Placeholder for when there's no direction control pin
+pub struct NoPin;
+
+impl OutputPin for NoPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// Silent interval, in microseconds, that delimits RTU frames (3.5
+/// characters)
+fn frame_gap_us(baud: u32) -> u32 {
+    if baud > 19_200 {
+        1_750
+    } else {
+        // 11 bits per character
+        35_000_000 / 10 * 11 / baud
+    }
+}
+
+/// Serial port plus direction pin
+struct Port<S, P> {
+    serial: S,
+    de: P,
+    gap_us: u32,
+    /// `micros()` at the end of the last frame sent or received
+    last_us: u32,
+}
+
+impl<S, P> Port<S, P>
+where
+    S: Read<u8> + Write<u8>,
+    P: OutputPin,
+{
+    fn send(&mut self, frame: &mut [u8], len: usize) -> Result<(), Error> {
+        let crc = crc16(&frame[..len]);
+        frame[len] = crc as u8;
+        frame[len + 1] = (crc >> 8) as u8;
+
+        // enforce the silent interval between frames
+        while ::micros().wrapping_sub(self.last_us) < self.gap_us {}
+
+        // discard any stale input
+        while self.serial.read().is_ok() {}
+
+        self.de.set_high().map_err(|_| Error::Serial)?;
+        let mut result = Ok(());
+        for &byte in &frame[..len + 2] {
+            result = nb::block!(self.serial.write(byte));
+            if result.is_err() {
+                break;
+            }
+        }
+        if result.is_ok() {
+            result = nb::block!(self.serial.flush());
+        }
+        self.de.set_low().map_err(|_| Error::Serial)?;
+        self.last_us = ::micros();
+
+        result.map_err(|_| Error::Serial)
+    }
+
+    /// Appends the available input to `frame`; returns `true` once the
+    /// frame is complete
+    fn receive(&mut self, frame: &mut [u8], len: &mut usize) -> bool {
+        loop {
+            match self.serial.read() {
+                Ok(byte) => {
+                    if *len < frame.len() {
+                        frame[*len] = byte;
+                        *len += 1;
+                    }
+                    self.last_us = ::micros();
+                }
+                Err(_) => {
+                    return *len > 0 &&
+                        ::micros().wrapping_sub(self.last_us) >= self.gap_us;
+                }
+            }
+        }
+    }
+}
+
+/// Checks the CRC of a received frame and returns its length without the
+/// CRC
+fn check_crc(frame: &[u8]) -> Option<usize> {
+    if frame.len() < 4 {
+        return None;
+    }
+
+    let len = frame.len() - 2;
+    let crc = u16::from(frame[len]) | u16::from(frame[len + 1]) << 8;
+    if crc16(&frame[..len]) == crc {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// Checks that the `quantity` addresses starting at `address` don't go past
+/// the end of the address space
+fn check_range(address: u16, quantity: usize) -> Result<(), Exception> {
+    if usize::from(address) + quantity > 0x1_0000 {
+        Err(Exception::IllegalDataAddress)
+    } else {
+        Ok(())
+    }
+}
+
+fn get_u16(bytes: &[u8], i: usize) -> u16 {
+    u16::from(bytes[i]) << 8 | u16::from(bytes[i + 1])
+}
+
+fn put_u16(bytes: &mut [u8], i: usize, value: u16) {
+    bytes[i] = (value >> 8) as u8;
+    bytes[i + 1] = value as u8;
+}
+
+/// Modbus RTU master
+pub struct Master<S, P> {
+    port: Port<S, P>,
+    timeout_ms: u32,
+    frame: [u8; MAX_FRAME_SIZE],
+}
+
+impl<S, P> Master<S, P>
+where
+    S: Read<u8> + Write<u8>,
+    P: OutputPin,
+{
+    /// Creates a master on a `serial` port running at `baud`, with `de` as
+    /// the direction control pin
+    pub fn new(serial: S, de: P, baud: u32) -> Self {
+        Master {
+            port: Port {
+                serial,
+                de,
+                gap_us: frame_gap_us(baud),
+                last_us: ::micros(),
+            },
+            timeout_ms: 1_000,
+            frame: [0; MAX_FRAME_SIZE],
+        }
+    }
+
+    /// Sets how long to wait for a reply; the default is 1 second
+    pub fn set_timeout(&mut self, ms: u32) {
+        self.timeout_ms = ms;
+    }
+
+    /// Releases the serial port and the direction pin
+    pub fn free(self) -> (S, P) {
+        (self.port.serial, self.port.de)
+    }
+
+    /// Reads `values.len()` coils starting at `address`
+    pub fn read_coils(
+        &mut self,
+        slave: u8,
+        address: u16,
+        values: &mut [bool],
+    ) -> Result<(), Error> {
+        self.read_bits(slave, READ_COILS, address, values)
+    }
+
+    /// Reads `values.len()` discrete inputs starting at `address`
+    pub fn read_discrete_inputs(
+        &mut self,
+        slave: u8,
+        address: u16,
+        values: &mut [bool],
+    ) -> Result<(), Error> {
+        self.read_bits(slave, READ_DISCRETE_INPUTS, address, values)
+    }
+
+    /// Reads `values.len()` holding registers starting at `address`
+    pub fn read_holding_registers(
+        &mut self,
+        slave: u8,
+        address: u16,
+        values: &mut [u16],
+    ) -> Result<(), Error> {
+        self.read_registers(slave, READ_HOLDING_REGISTERS, address, values)
+    }
+
+    /// Reads `values.len()` input registers starting at `address`
+    pub fn read_input_registers(
+        &mut self,
+        slave: u8,
+        address: u16,
+        values: &mut [u16],
+    ) -> Result<(), Error> {
+        self.read_registers(slave, READ_INPUT_REGISTERS, address, values)
+    }
+
+    /// Writes a single coil
+    pub fn write_single_coil(
+        &mut self,
+        slave: u8,
+        address: u16,
+        value: bool,
+    ) -> Result<(), Error> {
+        let value = if value { 0xFF00 } else { 0x0000 };
+        self.write_single(slave, WRITE_SINGLE_COIL, address, value)
+    }
+
+    /// Writes a single holding register
+    pub fn write_single_register(
+        &mut self,
+        slave: u8,
+        address: u16,
+        value: u16,
+    ) -> Result<(), Error> {
+        self.write_single(slave, WRITE_SINGLE_REGISTER, address, value)
+    }
+
+    /// Writes `values.len()` coils starting at `address`
+    pub fn write_multiple_coils(
+        &mut self,
+        slave: u8,
+        address: u16,
+        values: &[bool],
+    ) -> Result<(), Error> {
+        let bytes = values.len().div_ceil(8);
+        if values.is_empty() || values.len() > 1968 {
+            return Err(Error::InvalidRequest);
+        }
+
+        self.frame[0] = slave;
+        self.frame[1] = WRITE_MULTIPLE_COILS;
+        put_u16(&mut self.frame, 2, address);
+        put_u16(&mut self.frame, 4, values.len() as u16);
+        self.frame[6] = bytes as u8;
+        for byte in &mut self.frame[7..7 + bytes] {
+            *byte = 0;
+        }
+        for (i, &value) in values.iter().enumerate() {
+            if value {
+                self.frame[7 + i / 8] |= 1 << (i % 8);
+            }
+        }
+
+        self.write_multiple(slave, 7 + bytes, address, values.len())
+    }
+
+    /// Writes `values.len()` holding registers starting at `address`
+    pub fn write_multiple_registers(
+        &mut self,
+        slave: u8,
+        address: u16,
+        values: &[u16],
+    ) -> Result<(), Error> {
+        if values.is_empty() || values.len() > 123 {
+            return Err(Error::InvalidRequest);
+        }
+
+        self.frame[0] = slave;
+        self.frame[1] = WRITE_MULTIPLE_REGISTERS;
+        put_u16(&mut self.frame, 2, address);
+        put_u16(&mut self.frame, 4, values.len() as u16);
+        self.frame[6] = (values.len() * 2) as u8;
+        for (i, &value) in values.iter().enumerate() {
+            put_u16(&mut self.frame, 7 + 2 * i, value);
+        }
+
+        self.write_multiple(slave, 7 + 2 * values.len(), address, values.len())
+    }
+
+    fn read_bits(
+        &mut self,
+        slave: u8,
+        function: u8,
+        address: u16,
+        values: &mut [bool],
+    ) -> Result<(), Error> {
+        if values.is_empty() || values.len() > 2000 {
+            return Err(Error::InvalidRequest);
+        }
+
+        let bytes = values.len().div_ceil(8);
+        let len = self.request(slave, function, address, values.len())?;
+        if len != 3 + bytes || usize::from(self.frame[2]) != bytes {
+            return Err(Error::InvalidResponse);
+        }
+
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = self.frame[3 + i / 8] & 1 << (i % 8) != 0;
+        }
+
+        Ok(())
+    }
+
+    fn read_registers(
+        &mut self,
+        slave: u8,
+        function: u8,
+        address: u16,
+        values: &mut [u16],
+    ) -> Result<(), Error> {
+        if values.is_empty() || values.len() > 125 {
+            return Err(Error::InvalidRequest);
+        }
+
+        let len = self.request(slave, function, address, values.len())?;
+        if len != 3 + 2 * values.len() ||
+            usize::from(self.frame[2]) != 2 * values.len()
+        {
+            return Err(Error::InvalidResponse);
+        }
+
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = get_u16(&self.frame, 3 + 2 * i);
+        }
+
+        Ok(())
+    }
+
+    /// Sends a request with the `address, quantity` layout
+    fn request(
+        &mut self,
+        slave: u8,
+        function: u8,
+        address: u16,
+        quantity: usize,
+    ) -> Result<usize, Error> {
+        self.frame[0] = slave;
+        self.frame[1] = function;
+        put_u16(&mut self.frame, 2, address);
+        put_u16(&mut self.frame, 4, quantity as u16);
+        self.transact(6)
+    }
+
+    fn write_single(
+        &mut self,
+        slave: u8,
+        function: u8,
+        address: u16,
+        value: u16,
+    ) -> Result<(), Error> {
+        self.frame[0] = slave;
+        self.frame[1] = function;
+        put_u16(&mut self.frame, 2, address);
+        put_u16(&mut self.frame, 4, value);
+
+        if slave == BROADCAST {
+            return self.transact(6).map(|_| ());
+        }
+
+        // the reply echoes the request
+        if self.transact(6)? != 6 || get_u16(&self.frame, 2) != address ||
+            get_u16(&self.frame, 4) != value
+        {
+            return Err(Error::InvalidResponse);
+        }
+
+        Ok(())
+    }
+
+    fn write_multiple(
+        &mut self,
+        slave: u8,
+        len: usize,
+        address: u16,
+        quantity: usize,
+    ) -> Result<(), Error> {
+        if slave == BROADCAST {
+            return self.transact(len).map(|_| ());
+        }
+
+        if self.transact(len)? != 6 || get_u16(&self.frame, 2) != address ||
+            usize::from(get_u16(&self.frame, 4)) != quantity
+        {
+            return Err(Error::InvalidResponse);
+        }
+
+        Ok(())
+    }
+
+    /// Sends the first `len` bytes of `frame` and receives the reply into
+    /// `frame`. Returns the length of the reply without the CRC
+    fn transact(&mut self, len: usize) -> Result<usize, Error> {
+        let slave = self.frame[0];
+        let function = self.frame[1];
+
+        self.port.send(&mut self.frame, len)?;
+
+        if slave == BROADCAST {
+            return Ok(0);
+        }
+
+        let start = ::millis();
+        let mut len = 0;
+        while !self.port.receive(&mut self.frame, &mut len) {
+            if len == 0 && ::millis().wrapping_sub(start) > self.timeout_ms {
+                return Err(Error::Timeout);
+            }
+        }
+
+        let len = check_crc(&self.frame[..len]).ok_or(Error::Crc)?;
+        if self.frame[0] != slave {
+            return Err(Error::InvalidResponse);
+        }
+
+        if self.frame[1] == function | 0x80 {
+            return Err(Error::Exception(Exception::from_code(self.frame[2])));
+        }
+
+        if self.frame[1] != function {
+            return Err(Error::InvalidResponse);
+        }
+
+        Ok(len)
+    }
+}
+
+/// Data model of a Modbus slave
+///
+/// The read and write methods default to replying with `IllegalDataAddress`
+pub trait Handler {
+    /// Reads one coil
+    fn read_coil(&mut self, address: u16) -> Result<bool, Exception> {
+        let _ = address;
+        Err(Exception::IllegalDataAddress)
+    }
+
+    /// Reads one discrete input
+    fn read_discrete_input(&mut self, address: u16) -> Result<bool, Exception> {
+        let _ = address;
+        Err(Exception::IllegalDataAddress)
+    }
+
+    /// Reads one holding register
+    fn read_holding_register(
+        &mut self,
+        address: u16,
+    ) -> Result<u16, Exception> {
+        let _ = address;
+        Err(Exception::IllegalDataAddress)
+    }
+
+    /// Reads one input register
+    fn read_input_register(&mut self, address: u16) -> Result<u16, Exception> {
+        let _ = address;
+        Err(Exception::IllegalDataAddress)
+    }
+
+    /// Writes one coil
+    fn write_coil(
+        &mut self,
+        address: u16,
+        value: bool,
+    ) -> Result<(), Exception> {
+        let _ = (address, value);
+        Err(Exception::IllegalDataAddress)
+    }
+
+    /// Writes one holding register
+    fn write_register(
+        &mut self,
+        address: u16,
+        value: u16,
+    ) -> Result<(), Exception> {
+        let _ = (address, value);
+        Err(Exception::IllegalDataAddress)
+    }
+
+    /// Checks that the `quantity` coils starting at `address` can be written
+    ///
+    /// Called before writing multiple coils so that the request is either
+    /// applied entirely or not at all; `write_coil` is then expected to
+    /// succeed for each of them. Defaults to reading each coil
+    fn check_coils(
+        &mut self,
+        address: u16,
+        quantity: u16,
+    ) -> Result<(), Exception> {
+        for i in 0..quantity {
+            self.read_coil(address.wrapping_add(i))?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the `quantity` holding registers starting at `address`
+    /// can be written
+    ///
+    /// Called before writing multiple registers so that the request is
+    /// either applied entirely or not at all; `write_register` is then
+    /// expected to succeed for each of them. Defaults to reading each
+    /// register
+    fn check_registers(
+        &mut self,
+        address: u16,
+        quantity: u16,
+    ) -> Result<(), Exception> {
+        for i in 0..quantity {
+            self.read_holding_register(address.wrapping_add(i))?;
+        }
+        Ok(())
+    }
+}
+
+/// Modbus RTU slave
+pub struct Slave<S, P> {
+    port: Port<S, P>,
+    address: u8,
+    frame: [u8; MAX_FRAME_SIZE],
+    len: usize,
+}
+
+impl<S, P> Slave<S, P>
+where
+    S: Read<u8> + Write<u8>,
+    P: OutputPin,
+{
+    /// Creates a slave with the given `address` on a `serial` port running
+    /// at `baud`, with `de` as the direction control pin
+    pub fn new(serial: S, de: P, baud: u32, address: u8) -> Self {
+        Slave {
+            port: Port {
+                serial,
+                de,
+                gap_us: frame_gap_us(baud),
+                last_us: ::micros(),
+            },
+            address,
+            frame: [0; MAX_FRAME_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Releases the serial port and the direction pin
+    pub fn free(self) -> (S, P) {
+        (self.port.serial, self.port.de)
+    }
+
+    /// Processes the available input and replies to complete requests
+    ///
+    /// This doesn't block; call it from the application loop often enough
+    /// that the receive buffer of the serial port doesn't overflow
+    pub fn poll<H>(&mut self, handler: &mut H) -> Result<(), Error>
+    where
+        H: Handler,
+    {
+        if !self.port.receive(&mut self.frame, &mut self.len) {
+            return Ok(());
+        }
+
+        let len = self.len;
+        self.len = 0;
+
+        let len = match check_crc(&self.frame[..len]) {
+            Some(len) => len,
+            // corrupted frames are silently ignored
+            None => return Ok(()),
+        };
+
+        let slave = self.frame[0];
+        if slave != self.address && slave != BROADCAST {
+            return Ok(());
+        }
+
+        let function = self.frame[1];
+        let reply = match self.process(len, handler) {
+            Ok(len) => len,
+            Err(exception) => {
+                self.frame[1] = function | 0x80;
+                self.frame[2] = exception.code();
+                3
+            }
+        };
+
+        if slave == BROADCAST {
+            return Ok(());
+        }
+
+        self.port.send(&mut self.frame, reply)
+    }
+
+    /// Executes the request in `frame` and writes the reply in its place.
+    /// Returns the length of the reply without the CRC
+    fn process<H>(
+        &mut self,
+        len: usize,
+        handler: &mut H,
+    ) -> Result<usize, Exception>
+    where
+        H: Handler,
+    {
+        if len < 6 {
+            return Err(Exception::IllegalDataValue);
+        }
+
+        let function = self.frame[1];
+        let address = get_u16(&self.frame, 2);
+        let value = get_u16(&self.frame, 4);
+        let quantity = usize::from(value);
+
+        match function {
+            READ_COILS | READ_DISCRETE_INPUTS => {
+                if quantity == 0 || quantity > 2000 {
+                    return Err(Exception::IllegalDataValue);
+                }
+                check_range(address, quantity)?;
+
+                let bytes = quantity.div_ceil(8);
+                self.frame[2] = bytes as u8;
+                for i in 0..quantity {
+                    if i % 8 == 0 {
+                        self.frame[3 + i / 8] = 0;
+                    }
+
+                    let address = address + i as u16;
+                    let bit = if function == READ_COILS {
+                        handler.read_coil(address)?
+                    } else {
+                        handler.read_discrete_input(address)?
+                    };
+
+                    if bit {
+                        self.frame[3 + i / 8] |= 1 << (i % 8);
+                    }
+                }
+
+                Ok(3 + bytes)
+            }
+            READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+                if quantity == 0 || quantity > 125 {
+                    return Err(Exception::IllegalDataValue);
+                }
+                check_range(address, quantity)?;
+
+                self.frame[2] = (2 * quantity) as u8;
+                for i in 0..quantity {
+                    let address = address + i as u16;
+                    let value = if function == READ_HOLDING_REGISTERS {
+                        handler.read_holding_register(address)?
+                    } else {
+                        handler.read_input_register(address)?
+                    };
+
+                    put_u16(&mut self.frame, 3 + 2 * i, value);
+                }
+
+                Ok(3 + 2 * quantity)
+            }
+            WRITE_SINGLE_COIL => {
+                let value = match value {
+                    0xFF00 => true,
+                    0x0000 => false,
+                    _ => return Err(Exception::IllegalDataValue),
+                };
+
+                handler.write_coil(address, value)?;
+
+                // echo the request
+                Ok(6)
+            }
+            WRITE_SINGLE_REGISTER => {
+                handler.write_register(address, value)?;
+
+                // echo the request
+                Ok(6)
+            }
+            WRITE_MULTIPLE_COILS => {
+                let bytes = quantity.div_ceil(8);
+                if quantity == 0 || quantity > 1968 || len < 7 + bytes ||
+                    usize::from(self.frame[6]) != bytes
+                {
+                    return Err(Exception::IllegalDataValue);
+                }
+
+                check_range(address, quantity)?;
+                handler.check_coils(address, value)?;
+
+                for i in 0..quantity {
+                    let bit = self.frame[7 + i / 8] & 1 << (i % 8) != 0;
+                    handler.write_coil(address + i as u16, bit)?;
+                }
+
+                Ok(6)
+            }
+            WRITE_MULTIPLE_REGISTERS => {
+                if quantity == 0 || quantity > 123 || len < 7 + 2 * quantity ||
+                    usize::from(self.frame[6]) != 2 * quantity
+                {
+                    return Err(Exception::IllegalDataValue);
+                }
+
+                check_range(address, quantity)?;
+                handler.check_registers(address, value)?;
+
+                for i in 0..quantity {
+                    let value = get_u16(&self.frame, 7 + 2 * i);
+                    handler.write_register(address + i as u16, value)?;
+                }
+
+                Ok(6)
+            }
+            _ => Err(Exception::IllegalFunction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serial port that never receives anything
+    struct Idle;
+
+    impl Read<u8> for Idle {
+        type Error = ();
+
+        fn read(&mut self) -> nb::Result<u8, ()> {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    impl Write<u8> for Idle {
+        type Error = ();
+
+        fn write(&mut self, _: u8) -> nb::Result<(), ()> {
+            Ok(())
+        }
+
+        fn flush(&mut self) -> nb::Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    struct Registers {
+        coils: u16,
+        registers: [u16; 4],
+    }
+
+    impl Handler for Registers {
+        fn read_coil(&mut self, address: u16) -> Result<bool, Exception> {
+            if address < 16 {
+                Ok(self.coils & 1 << address != 0)
+            } else {
+                Err(Exception::IllegalDataAddress)
+            }
+        }
+
+        fn read_holding_register(
+            &mut self,
+            address: u16,
+        ) -> Result<u16, Exception> {
+            self.registers
+                .get(usize::from(address))
+                .cloned()
+                .ok_or(Exception::IllegalDataAddress)
+        }
+
+        fn write_coil(
+            &mut self,
+            address: u16,
+            value: bool,
+        ) -> Result<(), Exception> {
+            if address >= 16 {
+                return Err(Exception::IllegalDataAddress);
+            }
+
+            if value {
+                self.coils |= 1 << address;
+            } else {
+                self.coils &= !(1 << address);
+            }
+            Ok(())
+        }
+
+        fn write_register(
+            &mut self,
+            address: u16,
+            value: u16,
+        ) -> Result<(), Exception> {
+            match self.registers.get_mut(usize::from(address)) {
+                Some(register) => {
+                    *register = value;
+                    Ok(())
+                }
+                None => Err(Exception::IllegalDataAddress),
+            }
+        }
+    }
+
+    fn slave() -> Slave<Idle, NoPin> {
+        Slave {
+            port: Port {
+                serial: Idle,
+                de: NoPin,
+                gap_us: frame_gap_us(9_600),
+                last_us: 0,
+            },
+            address: 1,
+            frame: [0; MAX_FRAME_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Runs `request` (without the CRC) through `process` and returns the
+    /// reply
+    fn process<'a>(
+        slave: &'a mut Slave<Idle, NoPin>,
+        handler: &mut Registers,
+        request: &[u8],
+    ) -> Result<&'a [u8], Exception> {
+        slave.frame[..request.len()].copy_from_slice(request);
+        let len = slave.process(request.len(), handler)?;
+        Ok(&slave.frame[..len])
+    }
+
+    fn registers() -> Registers {
+        Registers {
+            coils: 0b1000_0000_0000_0101,
+            registers: [0x1234, 0x5678, 0x9ABC, 0xDEF0],
+        }
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc16(b"123456789"), 0x4B37);
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]), 0xCDC5);
+        assert_eq!(crc16(&[]), 0xFFFF);
+    }
+
+    #[test]
+    fn check_frame_crc() {
+        let frame = [0x01, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xCD];
+        assert_eq!(check_crc(&frame), Some(6));
+
+        let mut corrupted = frame;
+        corrupted[3] ^= 0x10;
+        assert_eq!(check_crc(&corrupted), None);
+
+        // swapped CRC bytes
+        let swapped = [0x01, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xCD, 0xC5];
+        assert_eq!(check_crc(&swapped), None);
+
+        assert_eq!(check_crc(&frame[..3]), None);
+    }
+
+    #[test]
+    fn big_endian_fields() {
+        let mut bytes = [0; 4];
+        put_u16(&mut bytes, 1, 0xABCD);
+        assert_eq!(bytes, [0x00, 0xAB, 0xCD, 0x00]);
+        assert_eq!(get_u16(&bytes, 1), 0xABCD);
+    }
+
+    #[test]
+    fn exception_codes() {
+        for code in 1..10 {
+            assert_eq!(Exception::from_code(code).code(), code);
+        }
+
+        assert_eq!(Exception::from_code(2), Exception::IllegalDataAddress);
+        assert_eq!(Exception::from_code(11), Exception::Other(11));
+    }
+
+    #[test]
+    fn read_requests() {
+        let mut slave = slave();
+        let mut handler = registers();
+
+        // 16 coils starting at 0: LSB first
+        assert_eq!(
+            process(&mut slave, &mut handler, &[1, 0x01, 0, 0, 0, 16]),
+            Ok(&[1, 0x01, 2, 0b0000_0101, 0b1000_0000][..])
+        );
+
+        // partial last byte
+        assert_eq!(
+            process(&mut slave, &mut handler, &[1, 0x01, 0, 1, 0, 3]),
+            Ok(&[1, 0x01, 1, 0b0000_0010][..])
+        );
+
+        assert_eq!(
+            process(&mut slave, &mut handler, &[1, 0x03, 0, 1, 0, 2]),
+            Ok(&[1, 0x03, 4, 0x56, 0x78, 0x9A, 0xBC][..])
+        );
+    }
+
+    #[test]
+    fn write_requests() {
+        let mut slave = slave();
+        let mut handler = registers();
+
+        let request = [1, 0x05, 0, 3, 0xFF, 0x00];
+        assert_eq!(
+            process(&mut slave, &mut handler, &request),
+            Ok(&request[..])
+        );
+        assert_eq!(handler.coils, 0b1000_0000_0000_1101);
+
+        let request = [1, 0x06, 0, 2, 0xCA, 0xFE];
+        assert_eq!(
+            process(&mut slave, &mut handler, &request),
+            Ok(&request[..])
+        );
+        assert_eq!(handler.registers[2], 0xCAFE);
+
+        // 10 coils starting at 4; all set but the last one
+        let request = [1, 0x0F, 0, 4, 0, 10, 2, 0b1111_1111, 0b0000_0001];
+        assert_eq!(
+            process(&mut slave, &mut handler, &request),
+            Ok(&request[..6])
+        );
+        assert_eq!(handler.coils, 0b1001_1111_1111_1101);
+
+        let request = [1, 0x10, 0, 0, 0, 2, 4, 0, 1, 0, 2];
+        assert_eq!(
+            process(&mut slave, &mut handler, &request),
+            Ok(&request[..6])
+        );
+        assert_eq!(handler.registers, [1, 2, 0xCAFE, 0xDEF0]);
+    }
+
+    #[test]
+    fn exceptions() {
+        let mut slave = slave();
+        let mut handler = registers();
+
+        assert_eq!(
+            process(&mut slave, &mut handler, &[1, 0x2B, 0, 0, 0, 1]),
+            Err(Exception::IllegalFunction)
+        );
+        assert_eq!(
+            process(&mut slave, &mut handler, &[1, 0x03, 0, 3, 0, 2]),
+            Err(Exception::IllegalDataAddress)
+        );
+        assert_eq!(
+            process(&mut slave, &mut handler, &[1, 0x03, 0, 0, 0, 0]),
+            Err(Exception::IllegalDataValue)
+        );
+        assert_eq!(
+            process(&mut slave, &mut handler, &[1, 0x05, 0, 0, 0x12, 0x34]),
+            Err(Exception::IllegalDataValue)
+        );
+
+        // byte count doesn't match the quantity
+        assert_eq!(
+            process(&mut slave, &mut handler, &[1, 0x10, 0, 0, 0, 1, 4, 0, 1]),
+            Err(Exception::IllegalDataValue)
+        );
+
+        // truncated request
+        assert_eq!(
+            process(&mut slave, &mut handler, &[1, 0x03, 0, 0]),
+            Err(Exception::IllegalDataValue)
+        );
+    }
+
+    #[test]
+    fn multiple_writes_are_atomic() {
+        let mut slave = slave();
+        let mut handler = registers();
+
+        // the last register doesn't exist
+        assert_eq!(
+            process(
+                &mut slave,
+                &mut handler,
+                &[1, 0x10, 0, 2, 0, 3, 6, 0, 1, 0, 2, 0, 3],
+            ),
+            Err(Exception::IllegalDataAddress)
+        );
+        assert_eq!(handler.registers, [0x1234, 0x5678, 0x9ABC, 0xDEF0]);
+
+        // 8 coils starting at 12
+        assert_eq!(
+            process(&mut slave, &mut handler, &[1, 0x0F, 0, 12, 0, 8, 1, 0]),
+            Err(Exception::IllegalDataAddress)
+        );
+        assert_eq!(handler.coils, 0b1000_0000_0000_0101);
+
+        // past the end of the address space
+        assert_eq!(
+            process(&mut slave, &mut handler, &[1, 0x03, 0xFF, 0xFF, 0, 2]),
+            Err(Exception::IllegalDataAddress)
+        );
+    }
+
+    #[test]
+    fn frame_gap() {
+        // 3.5 characters of 11 bits
+        assert_eq!(frame_gap_us(9_600), 4_010);
+        assert_eq!(frame_gap_us(115_200), 1_750);
+    }
+}
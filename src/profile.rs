@@ -0,0 +1,266 @@
+//! Cycle and latency profiling
+
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+use core::ptr;
+
+use UsbSerial;
+
+/// Maximum number of labels a `Profiler` can track
+pub const MAX_LABELS: usize = 16;
+
+/// Number of histogram buckets
+///
+/// Bucket `0` counts zero samples, bucket `i` counts samples in the
+/// `2^(i-1)..2^i` range and the last bucket counts everything above that
+pub const BUCKETS: usize = 16;
+
+// Cortex-M3 debug registers
+const DEMCR: *mut u32 = 0xE000_EDFC as *mut u32;
+const DEMCR_TRCENA: u32 = 1 << 24;
+const DWT_CTRL: *mut u32 = 0xE000_1000 as *mut u32;
+const DWT_CTRL_CYCCNTENA: u32 = 1;
+const DWT_CYCCNT: *const u32 = 0xE000_1004 as *const u32;
+
+/// Enables the DWT cycle counter
+///
+/// This must be called before using `cycles` or a `Clock::Cycles` profiler
+pub fn enable_cycle_counter() {
+    unsafe {
+        ptr::write_volatile(DEMCR, ptr::read_volatile(DEMCR) | DEMCR_TRCENA);
+        ptr::write_volatile(
+            DWT_CTRL,
+            ptr::read_volatile(DWT_CTRL) | DWT_CTRL_CYCCNTENA,
+        );
+    }
+}
+
+/// Returns the current value of the DWT cycle counter
+///
+/// At 120 MHz the counter wraps around every ~35 seconds
+pub fn cycles() -> u32 {
+    unsafe { ptr::read_volatile(DWT_CYCCNT) }
+}
+
+/// Time source of a `Profiler`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Clock {
+    /// Microseconds, from `micros()`
+    Micros,
+    /// CPU cycles, from the DWT cycle counter
+    Cycles,
+}
+
+impl Clock {
+    fn now(&self) -> u32 {
+        match *self {
+            Clock::Micros => ::micros(),
+            Clock::Cycles => cycles(),
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match *self {
+            Clock::Micros => "us",
+            Clock::Cycles => "cycles",
+        }
+    }
+}
+
+/// Samples recorded under a label
+#[derive(Clone, Copy, Debug)]
+pub struct Stats {
+    /// Number of samples
+    pub count: u32,
+    /// Shortest sample
+    pub min: u32,
+    /// Longest sample
+    pub max: u32,
+    /// Sum of all the samples
+    pub total: u64,
+    /// Distribution of the samples, see `BUCKETS`
+    pub histogram: [u32; BUCKETS],
+}
+
+impl Stats {
+    const EMPTY: Stats = Stats {
+        count: 0,
+        min: u32::MAX,
+        max: 0,
+        total: 0,
+        histogram: [0; BUCKETS],
+    };
+
+    /// Average sample
+    pub fn avg(&self) -> u32 {
+        if self.count == 0 {
+            0
+        } else {
+            (self.total / u64::from(self.count)) as u32
+        }
+    }
+
+    fn record(&mut self, sample: u32) {
+        self.count = self.count.saturating_add(1);
+        self.total += u64::from(sample);
+
+        if sample < self.min {
+            self.min = sample;
+        }
+
+        if sample > self.max {
+            self.max = sample;
+        }
+
+        let bucket = (32 - sample.leading_zeros()) as usize;
+        let bucket = if bucket < BUCKETS { bucket } else { BUCKETS - 1 };
+        self.histogram[bucket] = self.histogram[bucket].saturating_add(1);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    label: &'static str,
+    stats: Stats,
+}
+
+/// Collects timing statistics per label
+pub struct Profiler {
+    clock: Clock,
+    entries: RefCell<[Option<Entry>; MAX_LABELS]>,
+}
+
+impl Profiler {
+    /// Creates a profiler that measures time with `clock`
+    pub fn new(clock: Clock) -> Self {
+        Profiler {
+            clock,
+            entries: RefCell::new([None; MAX_LABELS]),
+        }
+    }
+
+    /// Starts timing a scope; the sample is recorded under `label` when the
+    /// returned value is dropped
+    pub fn scope<'a>(&'a self, label: &'static str) -> Scope<'a> {
+        Scope {
+            profiler: self,
+            label,
+            start: self.clock.now(),
+        }
+    }
+
+    /// Runs `f` and records how long it took under `label`
+    pub fn measure<F, R>(&self, label: &'static str, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let _scope = self.scope(label);
+        f()
+    }
+
+    /// Records a `sample` under `label`
+    ///
+    /// Samples are dropped if the profiler is already tracking `MAX_LABELS`
+    /// other labels
+    pub fn record(&self, label: &'static str, sample: u32) {
+        let mut entries = self.entries.borrow_mut();
+
+        let i = entries
+            .iter()
+            .position(|entry| match *entry {
+                Some(ref entry) => entry.label == label,
+                None => true,
+            });
+
+        if let Some(i) = i {
+            entries[i]
+                .get_or_insert(Entry {
+                    label,
+                    stats: Stats::EMPTY,
+                })
+                .stats
+                .record(sample);
+        }
+    }
+
+    /// Returns the statistics recorded under `label`
+    pub fn stats(&self, label: &str) -> Option<Stats> {
+        self.entries
+            .borrow()
+            .iter()
+            .filter_map(|entry| entry.as_ref())
+            .find(|entry| entry.label == label)
+            .map(|entry| entry.stats)
+    }
+
+    /// Discards all the recorded samples
+    pub fn reset(&self) {
+        *self.entries.borrow_mut() = [None; MAX_LABELS];
+    }
+
+    /// Writes a report of all the recorded statistics to `w`
+    pub fn dump<W>(&self, w: &mut W) -> fmt::Result
+    where
+        W: Write,
+    {
+        let unit = self.clock.unit();
+        write!(
+            w,
+            "{:<16} {:>8} {:>10} {:>10} {:>10} ({})\r\n",
+            "label",
+            "count",
+            "min",
+            "avg",
+            "max",
+            unit
+        )?;
+
+        for entry in self.entries.borrow().iter().filter_map(|e| e.as_ref()) {
+            let stats = &entry.stats;
+            write!(
+                w,
+                "{:<16} {:>8} {:>10} {:>10} {:>10}\r\n",
+                entry.label,
+                stats.count,
+                stats.min,
+                stats.avg(),
+                stats.max
+            )?;
+
+            for (i, &n) in stats.histogram.iter().enumerate() {
+                if n == 0 {
+                    continue;
+                }
+
+                if i == BUCKETS - 1 {
+                    write!(w, " >={}{}:{}", 1u32 << (i - 1), unit, n)?;
+                } else {
+                    write!(w, " <{}{}:{}", 1u32 << i, unit, n)?;
+                }
+            }
+
+            w.write_str("\r\n")?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a report of all the recorded statistics to the USB serial port
+    pub fn dump_serial(&self, mut serial: &UsbSerial) {
+        self.dump(&mut serial).ok();
+    }
+}
+
+/// A running scoped timer, see `Profiler::scope`
+pub struct Scope<'a> {
+    profiler: &'a Profiler,
+    label: &'static str,
+    start: u32,
+}
+
+impl<'a> Drop for Scope<'a> {
+    fn drop(&mut self) {
+        let elapsed = self.profiler.clock.now().wrapping_sub(self.start);
+        self.profiler.record(self.label, elapsed);
+    }
+}
@@ -0,0 +1,496 @@
+//! Wall clock job scheduler
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use cty::c_int;
+
+use ll::{c_void, system_event_t};
+use time::{DateTime, Zone};
+use {ll, time};
+
+/// Maximum number of jobs a `Scheduler` can hold
+pub const MAX_JOBS: usize = 8;
+
+/// Difference, in seconds, between the RTC and the monotonic clock that's
+/// treated as a change of the wall clock time
+const JUMP_TOLERANCE: i64 = 2;
+
+/// How far ahead, in days, `Cron::next_after` searches for a match
+const SEARCH_LIMIT: i64 = 5 * 366;
+
+const ALL_MINUTES: u64 = (1 << 60) - 1;
+const ALL_HOURS: u32 = (1 << 24) - 1;
+const ALL_DAYS: u32 = ((1 << 31) - 1) << 1;
+const ALL_MONTHS: u16 = ((1 << 12) - 1) << 1;
+const ALL_WEEKDAYS: u8 = (1 << 7) - 1;
+
+/// `* * * * *`
+const EVERY_MINUTE: Cron = Cron {
+    minutes: ALL_MINUTES,
+    hours: ALL_HOURS,
+    days: ALL_DAYS,
+    months: ALL_MONTHS,
+    weekdays: ALL_WEEKDAYS,
+};
+
+/// Set once the `time_changed` handler has been registered
+static SUBSCRIBED: AtomicBool = AtomicBool::new(false);
+
+/// Number of `time_changed` events seen so far
+static TIME_CHANGES: AtomicUsize = AtomicUsize::new(0);
+
+/// When a job runs
+#[derive(Clone, Copy, Debug)]
+pub enum Rule {
+    /// Every `ms` milliseconds, as measured by the monotonic clock
+    ///
+    /// These jobs are not affected by changes of the RTC
+    Interval(u32),
+    /// Whenever the wall clock time matches the cron expression
+    Cron(Cron),
+}
+
+/// A cron expression with minute resolution
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+}
+
+impl Cron {
+    /// Parses a standard five field cron expression
+    ///
+    /// `minute hour day-of-month month day-of-week`
+    ///
+    /// Each field accepts `*`, single values (`5`), ranges (`1-5`), steps
+    /// (`*/15`, `0-30/10`) and comma separated lists of those. Day of the
+    /// week goes from `0` (Sunday) to `7` (also Sunday). As in cron, if both
+    /// the day of the month and the day of the week are restricted, i.e.
+    /// don't cover every value, the job runs when *either* matches.
+    pub fn parse(expr: &str) -> Result<Self, ()> {
+        let mut fields = expr.split_whitespace();
+        let mut next = || fields.next().ok_or(());
+
+        let minute = next()?;
+        let hour = next()?;
+        let day = next()?;
+        let month = next()?;
+        let weekday = next()?;
+
+        if next().is_ok() {
+            return Err(());
+        }
+
+        let weekdays = field(weekday, 0, 7)?;
+
+        Ok(Cron {
+            minutes: field(minute, 0, 59)?,
+            hours: field(hour, 0, 23)? as u32,
+            days: field(day, 1, 31)? as u32,
+            months: field(month, 1, 12)? as u16,
+            // fold `7` into Sunday
+            weekdays: (weekdays | weekdays >> 7) as u8 & ALL_WEEKDAYS,
+        })
+    }
+
+    /// Every day at `hour`:`minute`
+    ///
+    /// # Panics
+    ///
+    /// If `hour` is not in `0..24` or `minute` is not in `0..60`
+    pub fn daily(hour: u8, minute: u8) -> Self {
+        assert!(hour < 24 && minute < 60);
+
+        Cron {
+            minutes: 1 << minute,
+            hours: 1 << hour,
+            ..EVERY_MINUTE
+        }
+    }
+
+    /// Every `n` minutes, starting at the top of the hour
+    ///
+    /// # Panics
+    ///
+    /// If `n` is not in `1..60`
+    pub fn every_minutes(n: u8) -> Self {
+        assert!(n > 0 && n < 60);
+
+        let mut minutes = 0;
+        let mut m = 0;
+        while m < 60 {
+            minutes |= 1 << m;
+            m += n;
+        }
+
+        Cron {
+            minutes,
+            ..EVERY_MINUTE
+        }
+    }
+
+    /// Returns the first time strictly after `t` that matches this
+    /// expression
+    ///
+    /// Both `t` and the returned value are local times: seconds since the
+    /// epoch as if the local time zone was UTC.
+    pub fn next_after(&self, t: i64) -> Option<i64> {
+        let mut t = (t / 60 + 1) * 60;
+        let limit = t + SEARCH_LIMIT * 24 * 60 * 60;
+
+        while t < limit {
+            let dt = DateTime::from_unix(t);
+            let midnight =
+                t - i64::from(dt.hour) * 3600 - i64::from(dt.minute) * 60;
+
+            if self.months & 1 << dt.month == 0 {
+                let (year, month) = if dt.month == 12 {
+                    (dt.year + 1, 1)
+                } else {
+                    (dt.year, dt.month + 1)
+                };
+
+                t = DateTime { year, month, day: 1, ..dt }
+                    .to_unix() - i64::from(dt.hour) * 3600 -
+                    i64::from(dt.minute) * 60;
+            } else if !self.matches_day(&dt) {
+                t = midnight + 24 * 60 * 60;
+            } else if self.hours & 1 << dt.hour == 0 {
+                t = midnight + (i64::from(dt.hour) + 1) * 3600;
+            } else if self.minutes & 1 << dt.minute == 0 {
+                t += 60;
+            } else {
+                return Some(t);
+            }
+        }
+
+        None
+    }
+
+    fn matches_day(&self, dt: &DateTime) -> bool {
+        let day = self.days & 1 << dt.day != 0;
+        let weekday = self.weekdays & 1 << dt.weekday != 0;
+
+        match (self.days == ALL_DAYS, self.weekdays == ALL_WEEKDAYS) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+/// Handle to a job registered in a `Scheduler`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct JobId(usize);
+
+#[derive(Clone, Copy)]
+struct Job {
+    rule: Rule,
+    f: fn(),
+    /// `Interval`: `millis()` of the last run. `Cron`: Unix time of the next
+    /// run
+    next: i64,
+    /// Unix time of the last run
+    last: i64,
+    /// `false` until `next` has been computed from a valid RTC
+    armed: bool,
+}
+
+/// Runs registered jobs at wall clock times
+///
+/// Call `poll` from the application loop. Changes of the RTC, like the ones
+/// caused by a cloud time sync, are detected either through the
+/// `time_changed` system event or by comparing the RTC against the monotonic
+/// clock. After a change, jobs whose time was skipped over run once and all
+/// the jobs are rescheduled relative to the new time; jobs never run twice
+/// for the same time slot when the clock moves backwards.
+pub struct Scheduler {
+    zone: Zone,
+    jobs: [Option<Job>; MAX_JOBS],
+    /// RTC at the last `poll`, if it was valid
+    rtc: Option<i64>,
+    /// `millis()` at the last `poll`
+    millis: u32,
+    /// `TIME_CHANGES` at the last `poll`
+    time_changes: usize,
+}
+
+impl Scheduler {
+    /// Creates a scheduler that evaluates cron expressions in `zone`
+    pub fn new(zone: Zone) -> Self {
+        Scheduler {
+            zone,
+            jobs: [None; MAX_JOBS],
+            rtc: None,
+            millis: 0,
+            time_changes: TIME_CHANGES.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Registers `f` to run according to `rule`
+    ///
+    /// Returns an error if the scheduler already holds `MAX_JOBS` jobs
+    pub fn add(&mut self, rule: Rule, f: fn()) -> Result<JobId, ()> {
+        let i = self.jobs.iter().position(|job| job.is_none()).ok_or(())?;
+
+        self.jobs[i] = Some(Job {
+            rule,
+            f,
+            next: i64::from(::millis()),
+            last: 0,
+            armed: false,
+        });
+
+        Ok(JobId(i))
+    }
+
+    /// Unregisters a job
+    pub fn remove(&mut self, id: JobId) {
+        self.jobs[id.0] = None;
+    }
+
+    /// Changes the time zone used to evaluate cron expressions
+    pub fn set_zone(&mut self, zone: Zone) {
+        self.zone = zone;
+
+        for job in self.jobs.iter_mut().filter_map(|job| job.as_mut()) {
+            if let Rule::Cron(_) = job.rule {
+                job.armed = false;
+            }
+        }
+    }
+
+    /// Runs the jobs that are due
+    pub fn poll(&mut self) {
+        if !SUBSCRIBED.swap(true, Ordering::Relaxed) {
+            unsafe {
+                ll::system_subscribe_event(
+                    ll::TIME_CHANGED,
+                    on_time_changed,
+                    ptr::null_mut(),
+                );
+            }
+        }
+
+        let ms = ::millis();
+        let rtc = if time::is_valid() {
+            Some(time::now())
+        } else {
+            None
+        };
+
+        let time_changes = TIME_CHANGES.load(Ordering::Relaxed);
+        let notified = time_changes != self.time_changes;
+        self.time_changes = time_changes;
+
+        let jumped = match (self.rtc, rtc) {
+            (Some(before), Some(now)) => {
+                let expected =
+                    before + i64::from(ms.wrapping_sub(self.millis) / 1000);
+                notified || (now - expected).abs() > JUMP_TOLERANCE
+            }
+            _ => false,
+        };
+
+        self.rtc = rtc;
+        self.millis = ms;
+
+        let zone = self.zone;
+        for job in self.jobs.iter_mut().filter_map(|job| job.as_mut()) {
+            match job.rule {
+                Rule::Interval(period) => {
+                    if ms.wrapping_sub(job.next as u32) >= period {
+                        job.next = i64::from(ms);
+                        (job.f)();
+                    }
+                }
+                Rule::Cron(ref cron) => {
+                    let now = match rtc {
+                        Some(now) => now,
+                        None => continue,
+                    };
+
+                    if !job.armed {
+                        job.next = next_run(cron, &zone, now);
+                        job.armed = true;
+                    } else if jumped {
+                        if job.next <= now {
+                            // catch up on the run that was skipped over
+                            job.last = now;
+                            (job.f)();
+                        }
+
+                        let from = if job.last > now { job.last } else { now };
+                        job.next = next_run(cron, &zone, from);
+                    } else if job.next <= now {
+                        job.last = job.next;
+                        job.next = next_run(cron, &zone, now);
+                        (job.f)();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Unix time of the next run of `cron` after Unix time `t`
+fn next_run(cron: &Cron, zone: &Zone, t: i64) -> i64 {
+    let local = t + i64::from(zone.local(t).offset);
+
+    match cron.next_after(local) {
+        Some(next) => {
+            let guess = next - i64::from(zone.offset);
+            next - i64::from(zone.local(guess).offset)
+        }
+        // never
+        None => i64::MAX,
+    }
+}
+
+extern "C" fn on_time_changed(_: system_event_t, _: c_int, _: *mut c_void) {
+    TIME_CHANGES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Parses a cron field into a bit mask
+fn field(s: &str, min: u8, max: u8) -> Result<u64, ()> {
+    let mut mask = 0;
+
+    for part in s.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(i) => (&part[..i], parse_u8(&part[i + 1..])?),
+            None => (part, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some(i) = range.find('-') {
+            (parse_u8(&range[..i])?, parse_u8(&range[i + 1..])?)
+        } else {
+            let start = parse_u8(range)?;
+            (start, if step == 1 { start } else { max })
+        };
+
+        if step == 0 || start < min || end > max || start > end {
+            return Err(());
+        }
+
+        let mut i = start;
+        while i <= end {
+            mask |= 1 << i;
+            i = match i.checked_add(step) {
+                Some(i) => i,
+                None => break,
+            };
+        }
+    }
+
+    Ok(mask)
+}
+
+fn parse_u8(s: &str) -> Result<u8, ()> {
+    s.parse().map_err(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u8, day: u8, hour: u8, minute: u8) -> i64 {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second: 0,
+            ..DateTime::from_unix(0)
+        }.to_unix()
+    }
+
+    fn next(expr: &str, t: i64) -> Option<i64> {
+        Cron::parse(expr).unwrap().next_after(t)
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Cron::parse("* * * * *"), Ok(EVERY_MINUTE));
+        assert_eq!(Cron::parse("*/15 * * * *"), Ok(Cron::every_minutes(15)));
+        assert_eq!(Cron::parse("30 7 * * *"), Ok(Cron::daily(7, 30)));
+        assert_eq!(
+            Cron::parse("0,30 8-17/3 * * *").map(|cron| cron.hours),
+            Ok(1 << 8 | 1 << 11 | 1 << 14 | 1 << 17)
+        );
+        // `7` is also Sunday
+        assert_eq!(Cron::parse("0 0 * * 7"), Cron::parse("0 0 * * 0"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Cron::parse("* * * *").is_err());
+        assert!(Cron::parse("* * * * * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("* * 0 * *").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+        assert!(Cron::parse("5-1 * * * *").is_err());
+        assert!(Cron::parse("a * * * *").is_err());
+    }
+
+    #[test]
+    fn next_after() {
+        assert_eq!(
+            next("30 7 * * *", at(2017, 1, 1, 0, 0)),
+            Some(at(2017, 1, 1, 7, 30))
+        );
+        assert_eq!(
+            next("*/15 * * * *", at(2017, 1, 1, 10, 7)),
+            Some(at(2017, 1, 1, 10, 15))
+        );
+        // strictly after
+        assert_eq!(
+            next("*/15 * * * *", at(2017, 1, 1, 10, 15)),
+            Some(at(2017, 1, 1, 10, 30))
+        );
+        assert_eq!(
+            next("0 12 * 2 *", at(2017, 1, 15, 0, 0)),
+            Some(at(2017, 2, 1, 12, 0))
+        );
+        assert_eq!(next("0 0 30 2 *", at(2017, 1, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        // the 1st of the month or Mondays; 2017-01-01 is a Sunday
+        assert_eq!(
+            next("0 0 1 * 1", at(2017, 1, 1, 0, 0)),
+            Some(at(2017, 1, 2, 0, 0))
+        );
+        assert_eq!(
+            next("0 0 1 * 1", at(2017, 1, 31, 0, 1)),
+            Some(at(2017, 2, 1, 0, 0))
+        );
+        // odd days or Mondays
+        assert_eq!(
+            next("0 0 */2 * 1", at(2017, 1, 2, 0, 0)),
+            Some(at(2017, 1, 3, 0, 0))
+        );
+    }
+
+    #[test]
+    fn unrestricted_day_fields() {
+        // a day of the month field that covers every day doesn't widen the
+        // day of the week field
+        assert_eq!(
+            next("0 0 1-31 * 1", at(2017, 1, 2, 0, 0)),
+            Some(at(2017, 1, 9, 0, 0))
+        );
+        assert_eq!(
+            next("0 0 1 * 0-6", at(2017, 1, 2, 0, 0)),
+            Some(at(2017, 2, 1, 0, 0))
+        );
+    }
+}
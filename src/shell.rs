@@ -0,0 +1,534 @@
+//! Line editing command shell
+
+use core::fmt::{self, Write};
+use core::str::{self, FromStr, SplitWhitespace};
+
+use {String, UsbSerial};
+
+/// Maximum length of a command line
+pub const LINE_SIZE: usize = 64;
+
+/// Number of command lines kept in the history
+pub const HISTORY_SIZE: usize = 8;
+
+const PROMPT: &str = "> ";
+
+/// A command that can be run from the shell or from a cloud function
+pub struct Command {
+    /// Name used to invoke the command
+    pub name: &'static str,
+    /// One line description, shown by `help`
+    pub help: &'static str,
+    /// The command itself; returns a status code where `0` means success
+    pub run: fn(&mut Args, &mut dyn Write) -> i32,
+}
+
+/// Arguments passed to a `Command`
+pub struct Args<'a> {
+    words: SplitWhitespace<'a>,
+}
+
+impl<'a> Args<'a> {
+    /// Returns the next argument and parses it as a `T`
+    ///
+    /// Returns an error if there are no arguments left or if the argument
+    /// can't be parsed
+    pub fn parse<T>(&mut self) -> Result<T, ()>
+    where
+        T: FromStr,
+    {
+        self.next().ok_or(())?.parse().map_err(|_| ())
+    }
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.words.next()
+    }
+}
+
+/// Runs the command `line` using the `commands` registry
+///
+/// `help` is always available and lists the registered commands. Returns the
+/// status code of the command, or an error if the command doesn't exist.
+pub fn dispatch(
+    commands: &[Command],
+    line: &str,
+    out: &mut dyn Write,
+) -> Result<i32, ()> {
+    let mut words = line.split_whitespace();
+    let name = match words.next() {
+        Some(name) => name,
+        None => return Ok(0),
+    };
+
+    let mut args = Args { words };
+    if let Some(command) = commands.iter().find(|c| c.name == name) {
+        return Ok((command.run)(&mut args, out));
+    }
+
+    if name == "help" {
+        for command in commands {
+            write!(out, "{:<12} {}\r\n", command.name, command.help).ok();
+        }
+        write!(out, "{:<12} lists the commands\r\n", "help").ok();
+        return Ok(0);
+    }
+
+    Err(())
+}
+
+/// Runs the command in the argument of a cloud function
+///
+/// This lets a cloud function expose the same `commands` as a `Shell`. The
+/// output of the command is discarded. Returns `-1` if the command doesn't
+/// exist
+///
+/// ``` ignore
+/// static COMMANDS: &[Command] = &[..];
+///
+/// extern "C" fn run(arg: &String, _: Cloud) -> i32 {
+///     shell::dispatch_cloud(COMMANDS, arg)
+/// }
+///
+/// cloud::function("run", run)
+/// ```
+pub fn dispatch_cloud(commands: &[Command], arg: &String) -> i32 {
+    struct Sink;
+
+    impl Write for Sink {
+        fn write_str(&mut self, _: &str) -> fmt::Result {
+            Ok(())
+        }
+    }
+
+    match str::from_utf8(arg) {
+        Ok(line) => dispatch(commands, line, &mut Sink).unwrap_or(-1),
+        Err(_) => -1,
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Line {
+    buffer: [u8; LINE_SIZE],
+    len: usize,
+}
+
+impl Line {
+    const EMPTY: Line = Line {
+        buffer: [0; LINE_SIZE],
+        len: 0,
+    };
+
+    fn as_str(&self) -> &str {
+        // only ASCII characters are stored
+        unsafe { str::from_utf8_unchecked(&self.buffer[..self.len]) }
+    }
+}
+
+/// Remembers whether the output ends with a newline
+struct Tracker<'w, W>
+where
+    W: Write + 'w,
+{
+    out: &'w mut W,
+    newline: bool,
+}
+
+impl<'w, W> Write for Tracker<'w, W>
+where
+    W: Write,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if let Some(last) = s.bytes().last() {
+            self.newline = last == b'\n';
+        }
+
+        self.out.write_str(s)
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Escape {
+    None,
+    /// Received `ESC`
+    Esc,
+    /// Received `ESC [` and maybe some parameter bytes
+    Csi,
+}
+
+/// Interactive shell
+///
+/// Supports backspace, `Ctrl-C` and `Ctrl-U` to discard the line, and the up
+/// and down arrows to browse the history.
+pub struct Shell<'a> {
+    commands: &'a [Command],
+    line: Line,
+    history: [Line; HISTORY_SIZE],
+    /// Number of lines stored in `history`
+    history_len: usize,
+    /// Index into `history` of the most recent line
+    history_head: usize,
+    /// How far back in the history we are; `0` is the line being edited
+    browsing: usize,
+    escape: Escape,
+    /// Whether the previous byte was a carriage return
+    cr: bool,
+}
+
+impl<'a> Shell<'a> {
+    /// Creates a shell that runs the given `commands`
+    pub fn new(commands: &'a [Command]) -> Self {
+        Shell {
+            commands,
+            line: Line::EMPTY,
+            history: [Line::EMPTY; HISTORY_SIZE],
+            history_len: 0,
+            history_head: 0,
+            browsing: 0,
+            escape: Escape::None,
+            cr: false,
+        }
+    }
+
+    /// Prints the prompt
+    pub fn prompt<W>(&self, out: &mut W)
+    where
+        W: Write,
+    {
+        out.write_str(PROMPT).ok();
+    }
+
+    /// Processes all the input available on the USB serial port
+    pub fn poll(&mut self, mut serial: &UsbSerial) {
+        while let Some(byte) = serial.read() {
+            self.feed(byte, &mut serial);
+        }
+    }
+
+    /// Processes one byte of input, echoing to `out`
+    pub fn feed<W>(&mut self, byte: u8, out: &mut W)
+    where
+        W: Write,
+    {
+        match self.escape {
+            Escape::Esc => {
+                self.escape = if byte == b'[' {
+                    Escape::Csi
+                } else {
+                    Escape::None
+                };
+                return;
+            }
+            Escape::Csi => {
+                match byte {
+                    // parameter and intermediate bytes, e.g. `1;5` in
+                    // `ESC [ 1 ; 5 A` (Ctrl-Up)
+                    0x20..=0x3f => return,
+                    b'A' => self.browse_up(out),
+                    b'B' => self.browse_down(out),
+                    _ => {}
+                }
+                self.escape = Escape::None;
+                return;
+            }
+            Escape::None => {}
+        }
+
+        let cr = self.cr;
+        self.cr = byte == b'\r';
+
+        match byte {
+            // ESC
+            0x1b => self.escape = Escape::Esc,
+            // `\r\n` is a single line ending
+            b'\n' if cr => {}
+            b'\r' | b'\n' => self.enter(out),
+            // Backspace, DEL
+            0x08 | 0x7f if self.line.len > 0 => {
+                self.line.len -= 1;
+                out.write_str("\x08 \x08").ok();
+            }
+            // Ctrl-C
+            0x03 => {
+                self.line.len = 0;
+                self.browsing = 0;
+                out.write_str("^C\r\n").ok();
+                self.prompt(out);
+            }
+            // Ctrl-U
+            0x15 => {
+                self.line.len = 0;
+                self.redraw(out);
+            }
+            b' '..=b'~' if self.line.len < LINE_SIZE => {
+                self.line.buffer[self.line.len] = byte;
+                self.line.len += 1;
+                out.write_char(byte as char).ok();
+            }
+            _ => {}
+        }
+    }
+
+    fn enter<W>(&mut self, out: &mut W)
+    where
+        W: Write,
+    {
+        out.write_str("\r\n").ok();
+
+        let line = self.line;
+        self.line.len = 0;
+        self.browsing = 0;
+
+        if !line.as_str().trim().is_empty() {
+            self.push_history(&line);
+
+            let mut tracker = Tracker {
+                out: &mut *out,
+                newline: true,
+            };
+            let result = dispatch(self.commands, line.as_str(), &mut tracker);
+
+            // don't let the output of the command run into the prompt
+            if !tracker.newline {
+                out.write_str("\r\n").ok();
+            }
+
+            match result {
+                Ok(0) => {}
+                Ok(code) => {
+                    write!(out, "exit code: {}\r\n", code).ok();
+                }
+                Err(()) => {
+                    out.write_str("unknown command; try `help`\r\n").ok();
+                }
+            }
+        }
+
+        self.prompt(out);
+    }
+
+    fn push_history(&mut self, line: &Line) {
+        if self.history_len > 0 &&
+            self.history[self.history_head].as_str() == line.as_str()
+        {
+            return;
+        }
+
+        self.history_head = (self.history_head + 1) % HISTORY_SIZE;
+        self.history[self.history_head] = *line;
+        if self.history_len < HISTORY_SIZE {
+            self.history_len += 1;
+        }
+    }
+
+    fn browse_up<W>(&mut self, out: &mut W)
+    where
+        W: Write,
+    {
+        if self.browsing < self.history_len {
+            self.browsing += 1;
+            self.recall(out);
+        }
+    }
+
+    fn browse_down<W>(&mut self, out: &mut W)
+    where
+        W: Write,
+    {
+        if self.browsing > 0 {
+            self.browsing -= 1;
+            if self.browsing == 0 {
+                self.line.len = 0;
+                self.redraw(out);
+            } else {
+                self.recall(out);
+            }
+        }
+    }
+
+    fn recall<W>(&mut self, out: &mut W)
+    where
+        W: Write,
+    {
+        let i = (self.history_head + HISTORY_SIZE - (self.browsing - 1)) %
+            HISTORY_SIZE;
+        self.line = self.history[i];
+        self.redraw(out);
+    }
+
+    fn redraw<W>(&self, out: &mut W)
+    where
+        W: Write,
+    {
+        write!(out, "\r\x1b[K{}{}", PROMPT, self.line.as_str()).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Output {
+        buffer: [u8; 512],
+        len: usize,
+    }
+
+    impl Output {
+        fn new() -> Self {
+            Output {
+                buffer: [0; 512],
+                len: 0,
+            }
+        }
+
+        fn as_str(&self) -> &str {
+            str::from_utf8(&self.buffer[..self.len]).unwrap()
+        }
+    }
+
+    impl Write for Output {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            if end > self.buffer.len() {
+                return Err(fmt::Error);
+            }
+
+            self.buffer[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    fn add(args: &mut Args, out: &mut dyn Write) -> i32 {
+        let mut sum = 0;
+        for arg in args {
+            match arg.parse::<i32>() {
+                Ok(n) => sum += n,
+                Err(_) => return 1,
+            }
+        }
+
+        write!(out, "{}", sum).ok();
+        0
+    }
+
+    fn scale(args: &mut Args, out: &mut dyn Write) -> i32 {
+        match (args.parse::<i32>(), args.parse::<i32>()) {
+            (Ok(a), Ok(b)) => {
+                write!(out, "{}", a * b).ok();
+                0
+            }
+            _ => 2,
+        }
+    }
+
+    const COMMANDS: &[Command] = &[
+        Command {
+            name: "add",
+            help: "adds numbers",
+            run: add,
+        },
+        Command {
+            name: "scale",
+            help: "multiplies two numbers",
+            run: scale,
+        },
+    ];
+
+    fn feed(shell: &mut Shell, input: &[u8], out: &mut Output) {
+        for &byte in input {
+            shell.feed(byte, out);
+        }
+    }
+
+    #[test]
+    fn dispatch_commands() {
+        let mut out = Output::new();
+
+        assert_eq!(dispatch(COMMANDS, "  add 1 2   3 ", &mut out), Ok(0));
+        assert_eq!(out.as_str(), "6");
+
+        assert_eq!(dispatch(COMMANDS, "add 1 x", &mut Output::new()), Ok(1));
+        assert_eq!(dispatch(COMMANDS, "", &mut Output::new()), Ok(0));
+        assert_eq!(dispatch(COMMANDS, "sub 1", &mut Output::new()), Err(()));
+    }
+
+    #[test]
+    fn args_parse() {
+        let mut out = Output::new();
+
+        assert_eq!(dispatch(COMMANDS, "scale 6 -7", &mut out), Ok(0));
+        assert_eq!(out.as_str(), "-42");
+
+        // missing and malformed arguments
+        let mut out = Output::new();
+        assert_eq!(dispatch(COMMANDS, "scale 6", &mut out), Ok(2));
+        assert_eq!(dispatch(COMMANDS, "scale 6 7.5", &mut out), Ok(2));
+        assert_eq!(out.as_str(), "");
+    }
+
+    #[test]
+    fn help() {
+        let mut out = Output::new();
+
+        assert_eq!(dispatch(COMMANDS, "help", &mut out), Ok(0));
+        assert_eq!(
+            out.as_str(),
+            "add          adds numbers\r\n\
+             scale        multiplies two numbers\r\n\
+             help         lists the commands\r\n"
+        );
+    }
+
+    #[test]
+    fn line_editing() {
+        let mut shell = Shell::new(COMMANDS);
+        let mut out = Output::new();
+
+        // backspace, then `\r\n` as a single line ending
+        feed(&mut shell, b"add 1 23\x08 4\r\n", &mut out);
+        assert_eq!(out.as_str(), "add 1 23\x08 \x08 4\r\n7\r\n> ");
+
+        // Ctrl-U discards the line
+        let mut out = Output::new();
+        feed(&mut shell, b"scale 1\x15add 5\r", &mut out);
+        assert!(out.as_str().ends_with("\r\n5\r\n> "));
+    }
+
+    #[test]
+    fn history() {
+        let mut shell = Shell::new(COMMANDS);
+
+        feed(&mut shell, b"add 1\radd 2\r", &mut Output::new());
+
+        // up twice, down once: the most recent line
+        let mut out = Output::new();
+        feed(&mut shell, b"\x1b[A\x1b[A\x1b[B\r", &mut out);
+        assert!(out.as_str().ends_with("add 2\r\n2\r\n> "));
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let mut shell = Shell::new(COMMANDS);
+
+        feed(&mut shell, b"add 1\r", &mut Output::new());
+
+        // Delete is ignored; Ctrl-Up browses like Up
+        let mut out = Output::new();
+        feed(&mut shell, b"add 2\x1b[3~\r\x1b[1;5A\x1b[1;5A\r", &mut out);
+        assert!(out.as_str().starts_with("add 2\r\n2\r\n> "));
+        assert!(out.as_str().ends_with("add 1\r\n1\r\n> "));
+    }
+
+    #[test]
+    fn unknown_command() {
+        let mut shell = Shell::new(COMMANDS);
+        let mut out = Output::new();
+
+        feed(&mut shell, b"sub\r", &mut out);
+        assert_eq!(out.as_str(), "sub\r\nunknown command; try `help`\r\n> ");
+    }
+}
@@ -0,0 +1,588 @@
+//! Hardware SPI
+//!
+//! The clock is configured with a target frequency; the closest frequency
+//! that's not faster than the target is used.
+//!
+//! Large transfers can run in the background using DMA. The buffers must be
+//! `'static`; a transfer takes them and hands them back when it completes.
+//! Borrowed buffers wouldn't be sound: a transfer can be leaked with
+//! `mem::forget`, which skips the cancellation in `Drop` and leaves the DMA
+//! writing to memory that's no longer borrowed.
+//!
+//!
+//! ``` ignore
+//! let transfer = Spi.dma_write(framebuffer, None);
+//!
+//! // .. do other work ..
+//!
+//! if transfer.is_done() {
+//!     let framebuffer = transfer.wait();
+//! }
+//! ```
+//!
+//! In slave mode the master drives the clock, so all the transfers go
+//! through DMA. A transfer completes when the master deselects the slave,
+//! or earlier if the buffers fill up:
+//!
+//! ``` ignore
+//! fn on_select(selected: bool) { .. }
+//!
+//! Spi1.begin_slave(Settings::default(), Some(on_select));
+//!
+//! let transfer = Spi1.dma_transfer(tx, rx, None);
+//! while !transfer.is_done() {}
+//! let n = transfer.transferred();
+//! let (tx, rx) = transfer.wait();
+//! ```
+
+use core::cell::UnsafeCell;
+use core::convert::Infallible;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::{mem, ptr};
+
+use hal::blocking::spi;
+use hal::spi::{Mode, Phase, Polarity, MODE_0};
+use ll;
+
+/// Order in which the bits of a byte are shifted out
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
+
+impl BitOrder {
+    fn bits(&self) -> u8 {
+        match *self {
+            BitOrder::MsbFirst => ll::MSBFIRST,
+            BitOrder::LsbFirst => ll::LSBFIRST,
+        }
+    }
+}
+
+/// Bus settings
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Settings {
+    /// Maximum clock frequency, in Hz
+    pub frequency: u32,
+    /// Clock polarity and phase
+    pub mode: Mode,
+    pub bit_order: BitOrder,
+}
+
+impl Default for Settings {
+    /// 1 MHz, mode 0, MSB first
+    fn default() -> Self {
+        Settings {
+            frequency: 1_000_000,
+            mode: MODE_0,
+            bit_order: BitOrder::MsbFirst,
+        }
+    }
+}
+
+fn data_mode(mode: &Mode) -> u8 {
+    match (mode.polarity, mode.phase) {
+        (Polarity::IdleLow, Phase::CaptureOnFirstTransition) => ll::SPI_MODE0,
+        (Polarity::IdleLow, Phase::CaptureOnSecondTransition) => ll::SPI_MODE1,
+        (Polarity::IdleHigh, Phase::CaptureOnFirstTransition) => ll::SPI_MODE2,
+        (Polarity::IdleHigh, Phase::CaptureOnSecondTransition) => {
+            ll::SPI_MODE3
+        }
+    }
+}
+
+/// Returns the divider setting, and the resulting frequency, for the
+/// fastest clock not faster than `frequency`
+fn clock_divider(system_clock: u32, frequency: u32) -> (u8, u32) {
+    let mut divider = ll::SPI_CLOCK_DIV2;
+    let mut clock = system_clock / 2;
+
+    // the slowest setting is `/256`
+    for _ in 0..7 {
+        if clock <= frequency {
+            break;
+        }
+
+        divider += ll::SPI_CLOCK_DIV_STEP;
+        clock /= 2;
+    }
+
+    (divider, clock)
+}
+
+/// Callback shared with an interrupt handler
+struct Callback<T>(UnsafeCell<Option<T>>);
+
+// only written while the interrupt can't fire: while no transfer is in
+// progress, or while the peripheral is disabled
+unsafe impl<T> Sync for Callback<T> {}
+
+/// State of the DMA transfers of one SPI peripheral
+struct Dma {
+    /// The latest transfer has completed
+    done: AtomicBool,
+    /// Identifies the latest transfer
+    generation: AtomicUsize,
+    callback: Callback<fn()>,
+}
+
+impl Dma {
+    const fn new() -> Self {
+        Dma {
+            done: AtomicBool::new(true),
+            generation: AtomicUsize::new(0),
+            callback: Callback(UnsafeCell::new(None)),
+        }
+    }
+
+    /// Called from the DMA interrupt handler
+    fn complete(&self) {
+        self.done.store(true, Ordering::Release);
+
+        if let Some(callback) = unsafe { *self.callback.0.get() } {
+            callback();
+        }
+    }
+}
+
+/// A DMA transfer in progress
+///
+/// Holds the buffers `B` until the transfer completes. Dropping an
+/// unfinished transfer cancels it.
+#[must_use = "dropping the transfer cancels it"]
+pub struct DmaTransfer<B> {
+    spi: ll::HAL_SPI_Interface,
+    dma: &'static Dma,
+    generation: usize,
+    buffers: Option<B>,
+}
+
+impl<B> DmaTransfer<B> {
+    /// Returns `true` if the transfer has completed
+    pub fn is_done(&self) -> bool {
+        // a newer transfer only starts after this one has completed
+        self.dma.generation.load(Ordering::Acquire) != self.generation ||
+            self.dma.done.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of bytes transferred so far
+    pub fn transferred(&self) -> usize {
+        let mut status = ll::HAL_SPI_TransferStatus {
+            configured_transfer_length: 0,
+            transfer_length: 0,
+            flags: 0,
+        };
+
+        unsafe {
+            ll::HAL_SPI_DMA_Transfer_Status(self.spi, &mut status);
+        }
+
+        status.transfer_length as usize
+    }
+
+    /// Waits until the transfer completes and returns the buffers
+    pub fn wait(mut self) -> B {
+        while !self.is_done() {}
+
+        self.buffers.take().unwrap()
+    }
+
+    /// Aborts the transfer and returns the buffers
+    pub fn cancel(mut self) -> B {
+        self.abort();
+
+        self.buffers.take().unwrap()
+    }
+
+    fn abort(&self) {
+        if !self.is_done() {
+            unsafe { ll::HAL_SPI_DMA_Transfer_Cancel(self.spi) }
+            self.dma.done.store(true, Ordering::Release);
+        }
+    }
+}
+
+impl<B> Drop for DmaTransfer<B> {
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
+
+macro_rules! spi {
+    (
+        $(#[$attr:meta])*
+        $Spi:ident,
+        $spi:ident,
+        $DMA:ident,
+        $dma:ident,
+        $SELECT:ident,
+        $select:ident
+    ) => {
+        static $DMA: Dma = Dma::new();
+        static $SELECT: Callback<fn(bool)> = Callback(UnsafeCell::new(None));
+
+        extern "C" fn $dma() {
+            $DMA.complete();
+        }
+
+        extern "C" fn $select(state: u8) {
+            if let Some(callback) = unsafe { *$SELECT.0.get() } {
+                callback(state != 0);
+            }
+        }
+
+        $(#[$attr])*
+        pub struct $Spi;
+
+        impl $Spi {
+            /// Enables the SPI peripheral as a master with the given
+            /// `settings`
+            ///
+            /// Returns the actual clock frequency
+            pub fn begin(&self, settings: Settings) -> u32 {
+                unsafe {
+                    ll::HAL_SPI_Init(ll::HAL_SPI_Interface::$spi);
+                    ll::HAL_SPI_Begin_Ext(
+                        ll::HAL_SPI_Interface::$spi,
+                        ll::SPI_Mode::SPI_MODE_MASTER,
+                        ll::SPI_DEFAULT_SS,
+                        ptr::null_mut(),
+                    );
+                }
+
+                self.configure(&settings)
+            }
+
+            /// Enables the SPI peripheral as a slave selected by the
+            /// default SS pin
+            ///
+            /// The clock frequency of the `settings` is ignored.
+            /// `on_select`, if any, runs in interrupt context with `true`
+            /// when the master selects the slave and `false` when it
+            /// deselects it.
+            pub fn begin_slave(
+                &self,
+                settings: Settings,
+                on_select: Option<fn(bool)>,
+            ) {
+                unsafe {
+                    *$SELECT.0.get() = on_select;
+
+                    ll::HAL_SPI_Init(ll::HAL_SPI_Interface::$spi);
+                    ll::HAL_SPI_Set_Callback_On_Select(
+                        ll::HAL_SPI_Interface::$spi,
+                        $select,
+                        ptr::null_mut(),
+                    );
+                    ll::HAL_SPI_Begin_Ext(
+                        ll::HAL_SPI_Interface::$spi,
+                        ll::SPI_Mode::SPI_MODE_SLAVE,
+                        ll::SPI_DEFAULT_SS,
+                        ptr::null_mut(),
+                    );
+                    ll::HAL_SPI_Set_Data_Mode(
+                        ll::HAL_SPI_Interface::$spi,
+                        data_mode(&settings.mode),
+                    );
+                    ll::HAL_SPI_Set_Bit_Order(
+                        ll::HAL_SPI_Interface::$spi,
+                        settings.bit_order.bits(),
+                    );
+                }
+            }
+
+            /// Disables the SPI peripheral
+            pub fn end(&self) {
+                unsafe { ll::HAL_SPI_End(ll::HAL_SPI_Interface::$spi) }
+            }
+
+            /// Returns `true` while the master selects this device
+            ///
+            /// Only meaningful in slave mode
+            pub fn is_selected(&self) -> bool {
+                let mut status = ll::HAL_SPI_TransferStatus {
+                    configured_transfer_length: 0,
+                    transfer_length: 0,
+                    flags: 0,
+                };
+
+                unsafe {
+                    ll::HAL_SPI_DMA_Transfer_Status(
+                        ll::HAL_SPI_Interface::$spi,
+                        &mut status,
+                    );
+                }
+
+                status.flags & ll::SPI_STATUS_SS_STATE != 0
+            }
+
+            /// Returns `true` if the SPI peripheral is enabled
+            pub fn is_enabled(&self) -> bool {
+                unsafe { ll::HAL_SPI_Is_Enabled(ll::HAL_SPI_Interface::$spi) }
+            }
+
+            /// Changes the bus settings
+            ///
+            /// Returns the actual clock frequency
+            pub fn configure(&self, settings: &Settings) -> u32 {
+                let (divider, clock) =
+                    clock_divider(self.system_clock(), settings.frequency);
+
+                unsafe {
+                    ll::HAL_SPI_Set_Settings(
+                        ll::HAL_SPI_Interface::$spi,
+                        0,
+                        divider,
+                        settings.bit_order.bits(),
+                        data_mode(&settings.mode),
+                        ptr::null_mut(),
+                    );
+                }
+
+                clock
+            }
+
+            /// Sets the clock polarity and phase
+            pub fn set_mode(&self, mode: Mode) {
+                unsafe {
+                    ll::HAL_SPI_Set_Data_Mode(
+                        ll::HAL_SPI_Interface::$spi,
+                        data_mode(&mode),
+                    )
+                }
+            }
+
+            /// Sets the bit order
+            pub fn set_bit_order(&self, bit_order: BitOrder) {
+                unsafe {
+                    ll::HAL_SPI_Set_Bit_Order(
+                        ll::HAL_SPI_Interface::$spi,
+                        bit_order.bits(),
+                    )
+                }
+            }
+
+            /// Sets the fastest clock not faster than `frequency`, in Hz
+            ///
+            /// Returns the actual clock frequency
+            pub fn set_frequency(&self, frequency: u32) -> u32 {
+                let (divider, clock) =
+                    clock_divider(self.system_clock(), frequency);
+
+                unsafe {
+                    ll::HAL_SPI_Set_Clock_Divider(
+                        ll::HAL_SPI_Interface::$spi,
+                        divider,
+                    )
+                }
+
+                clock
+            }
+
+            /// Returns the current clock frequency, in Hz
+            pub fn frequency(&self) -> u32 {
+                self.info().clock
+            }
+
+            /// Sends `byte` and returns the byte received at the same time
+            pub fn transfer_byte(&self, byte: u8) -> u8 {
+                unsafe {
+                    ll::HAL_SPI_Send_Receive_Data(
+                        ll::HAL_SPI_Interface::$spi,
+                        u16::from(byte),
+                    ) as u8
+                }
+            }
+
+            /// Sends the contents of `buffer`, replacing them with the
+            /// received bytes
+            pub fn transfer(&self, buffer: &mut [u8]) {
+                for byte in buffer {
+                    *byte = self.transfer_byte(*byte);
+                }
+            }
+
+            /// Sends `bytes`, discarding the received bytes
+            pub fn write(&self, bytes: &[u8]) {
+                for &byte in bytes {
+                    self.transfer_byte(byte);
+                }
+            }
+
+            /// Starts sending `bytes` in the background; the received bytes
+            /// are discarded
+            ///
+            /// `callback`, if any, runs in interrupt context when the
+            /// transfer completes. Waits for the previous transfer to
+            /// complete before starting.
+            pub fn dma_write(
+                &self,
+                bytes: &'static [u8],
+                callback: Option<fn()>,
+            ) -> DmaTransfer<&'static [u8]> {
+                let (tx, len) = (bytes.as_ptr(), bytes.len());
+                self.dma(tx, ptr::null_mut(), len, callback, bytes)
+            }
+
+            /// Starts filling `buffer` in the background; `0xFF` is sent
+            /// meanwhile
+            ///
+            /// See `dma_write` for `callback`
+            pub fn dma_read(
+                &self,
+                buffer: &'static mut [u8],
+                callback: Option<fn()>,
+            ) -> DmaTransfer<&'static mut [u8]> {
+                let (rx, len) = (buffer.as_mut_ptr(), buffer.len());
+                self.dma(ptr::null(), rx, len, callback, buffer)
+            }
+
+            /// Starts sending `tx` and receiving into `rx` in the
+            /// background
+            ///
+            /// See `dma_write` for `callback`
+            ///
+            /// # Panics
+            ///
+            /// If the buffers have different lengths
+            pub fn dma_transfer(
+                &self,
+                tx: &'static [u8],
+                rx: &'static mut [u8],
+                callback: Option<fn()>,
+            ) -> DmaTransfer<(&'static [u8], &'static mut [u8])> {
+                assert_eq!(tx.len(), rx.len());
+
+                let (tx_ptr, rx_ptr, len) =
+                    (tx.as_ptr(), rx.as_mut_ptr(), rx.len());
+                self.dma(tx_ptr, rx_ptr, len, callback, (tx, rx))
+            }
+
+            fn dma<B>(
+                &self,
+                tx: *const u8,
+                rx: *mut u8,
+                len: usize,
+                callback: Option<fn()>,
+                buffers: B,
+            ) -> DmaTransfer<B> {
+                while !$DMA.done.load(Ordering::Acquire) {}
+
+                unsafe {
+                    *$DMA.callback.0.get() = callback;
+                }
+                // retire the previous transfer before marking this one as
+                // ongoing, see `DmaTransfer::is_done`
+                let generation =
+                    $DMA.generation.fetch_add(1, Ordering::AcqRel)
+                        .wrapping_add(1);
+                $DMA.done.store(false, Ordering::Release);
+
+                if len == 0 {
+                    $DMA.complete();
+                } else {
+                    unsafe {
+                        ll::HAL_SPI_DMA_Transfer(
+                            ll::HAL_SPI_Interface::$spi,
+                            tx as *mut ll::c_void,
+                            rx as *mut ll::c_void,
+                            len as u32,
+                            $dma,
+                        );
+                    }
+                }
+
+                DmaTransfer {
+                    spi: ll::HAL_SPI_Interface::$spi,
+                    dma: &$DMA,
+                    generation,
+                    buffers: Some(buffers),
+                }
+            }
+
+            /// Clock the divider applies to, in Hz
+            fn system_clock(&self) -> u32 {
+                self.info().system_clock
+            }
+
+            fn info(&self) -> ll::hal_spi_info_t {
+                unsafe {
+                    // zeroed so that fields unknown to the HAL read back as 0
+                    let mut info: ll::hal_spi_info_t = mem::zeroed();
+                    info.version = ll::HAL_SPI_INFO_VERSION_2;
+                    ll::HAL_SPI_Info(
+                        ll::HAL_SPI_Interface::$spi,
+                        &mut info,
+                        ptr::null_mut(),
+                    );
+                    info
+                }
+            }
+        }
+
+        impl spi::Transfer<u8> for $Spi {
+            type Error = Infallible;
+
+            fn transfer<'w>(
+                &mut self,
+                buffer: &'w mut [u8],
+            ) -> Result<&'w [u8], Infallible> {
+                $Spi::transfer(self, buffer);
+                Ok(buffer)
+            }
+        }
+
+        impl spi::Write<u8> for $Spi {
+            type Error = Infallible;
+
+            fn write(&mut self, bytes: &[u8]) -> Result<(), Infallible> {
+                $Spi::write(self, bytes);
+                Ok(())
+            }
+        }
+    }
+}
+
+spi!(
+    /// Hardware SPI on the A3 (SCK), A4 (MISO), A5 (MOSI) and A2 (SS) pins
+    Spi,
+    HAL_SPI_INTERFACE1,
+    SPI_DMA,
+    spi_dma_complete,
+    SPI_SELECT,
+    spi_select
+);
+
+spi!(
+    /// Hardware SPI on the D4 (SCK), D3 (MISO), D2 (MOSI) and D5 (SS) pins
+    Spi1,
+    HAL_SPI_INTERFACE2,
+    SPI1_DMA,
+    spi1_dma_complete,
+    SPI1_SELECT,
+    spi1_select
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_divider_rounds_down() {
+        // SPI1 runs off the 60 MHz APB2 clock
+        assert_eq!(clock_divider(60_000_000, 30_000_000), (0x00, 30_000_000));
+        assert_eq!(clock_divider(60_000_000, 20_000_000), (0x08, 15_000_000));
+        assert_eq!(clock_divider(60_000_000, 15_000_000), (0x08, 15_000_000));
+        assert_eq!(clock_divider(30_000_000, 1_000_000), (0x20, 937_500));
+    }
+
+    #[test]
+    fn clock_divider_limits() {
+        // faster than the peripheral can go
+        assert_eq!(clock_divider(60_000_000, 100_000_000), (0x00, 30_000_000));
+        // slower than `/256`
+        assert_eq!(clock_divider(60_000_000, 1_000), (0x38, 234_375));
+        assert_eq!(clock_divider(60_000_000, 0), (0x38, 234_375));
+    }
+}
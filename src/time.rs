@@ -0,0 +1,562 @@
+//! Calendar time, time zones and formatting
+
+use core::fmt::{self, Write};
+use core::{ptr, str};
+
+use ll;
+
+/// `Time.format` default format: `Wed May 21 01:08:47 2014`
+pub const FORMAT_DEFAULT: &str = "%a %b %d %H:%M:%S %Y";
+
+/// ISO 8601 format with time zone offset: `2014-05-21T01:08:47-0500`
+pub const FORMAT_ISO8601_FULL: &str = "%Y-%m-%dT%H:%M:%S%z";
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+const DAY_NAMES: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Returns the current Unix time, as kept by the RTC
+pub fn now() -> i64 {
+    // `time_t` is 32-bit on the device
+    unsafe { ll::HAL_RTC_Get_UnixTime() as i64 }
+}
+
+/// Returns `true` if the RTC has been set, either by the cloud or by the
+/// application
+pub fn is_valid() -> bool {
+    unsafe { ll::HAL_RTC_Time_Is_Valid(ptr::null_mut()) != 0 }
+}
+
+/// A broken down calendar date and time
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DateTime {
+    /// Year, e.g. `2017`
+    pub year: i32,
+    /// Month of the year, `1..13`
+    pub month: u8,
+    /// Day of the month, `1..32`
+    pub day: u8,
+    /// Hours, `0..24`
+    pub hour: u8,
+    /// Minutes, `0..60`
+    pub minute: u8,
+    /// Seconds, `0..60`
+    pub second: u8,
+    /// Day of the week, `0..7` where `0` is Sunday
+    pub weekday: u8,
+    /// Day of the year, `1..367`
+    pub yday: u16,
+    /// Offset from UTC in seconds, including the DST offset
+    pub offset: i32,
+    /// Whether daylight saving time was in effect
+    pub dst: bool,
+}
+
+impl DateTime {
+    /// Breaks down the Unix time `t` into a UTC date and time
+    pub fn from_unix(t: i64) -> Self {
+        Self::from_unix_with_offset(t, 0, false)
+    }
+
+    fn from_unix_with_offset(t: i64, offset: i32, dst: bool) -> Self {
+        let local = t + i64::from(offset);
+        let days = div_floor(local, SECONDS_PER_DAY);
+        let secs = local - days * SECONDS_PER_DAY;
+
+        let (year, month, day) = civil_from_days(days);
+        let yday = days - days_from_civil(year, 1, 1) + 1;
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+            weekday: weekday(days),
+            yday: yday as u16,
+            offset,
+            dst,
+        }
+    }
+
+    /// Converts this date and time back into Unix time
+    pub fn to_unix(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * SECONDS_PER_DAY +
+            i64::from(self.hour) * 3600 +
+            i64::from(self.minute) * 60 + i64::from(self.second) -
+            i64::from(self.offset)
+    }
+
+    /// Formats this date and time into `buffer` according to `format`
+    ///
+    /// The following `strftime` conversion specifiers are supported: `%a`,
+    /// `%A`, `%b`, `%h`, `%B`, `%c`, `%d`, `%e`, `%F`, `%H`, `%I`, `%j`, `%m`,
+    /// `%M`, `%n`, `%p`, `%R`, `%s`, `%S`, `%t`, `%T`, `%u`, `%w`, `%y`, `%Y`,
+    /// `%z` and `%%`.
+    ///
+    /// Returns an error if `buffer` is too small, if `format` contains an
+    /// unsupported specifier or if `month`, `weekday` or `hour` is out of
+    /// range.
+    pub fn format<'b>(
+        &self,
+        format: &str,
+        buffer: &'b mut [u8],
+    ) -> Result<&'b str, ()> {
+        let len = {
+            let mut cursor = Cursor {
+                buffer,
+                len: 0,
+            };
+            self.write(format, &mut cursor).map_err(|_| ())?;
+            cursor.len
+        };
+
+        Ok(unsafe { str::from_utf8_unchecked(&buffer[..len]) })
+    }
+
+    /// Formats this date and time into `w` according to `format`
+    ///
+    /// See `format` for the list of supported specifiers and the errors
+    pub fn write<W>(&self, format: &str, w: &mut W) -> fmt::Result
+    where
+        W: Write,
+    {
+        if !(1..=12).contains(&self.month) || self.weekday > 6 ||
+            self.hour > 23
+        {
+            return Err(fmt::Error);
+        }
+
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                w.write_char(c)?;
+                continue;
+            }
+
+            match chars.next() {
+                Some('a') => w.write_str(&self.day_name()[..3])?,
+                Some('A') => w.write_str(self.day_name())?,
+                Some('b') | Some('h') => w.write_str(&self.month_name()[..3])?,
+                Some('B') => w.write_str(self.month_name())?,
+                Some('c') => self.write(FORMAT_DEFAULT, w)?,
+                Some('d') => write!(w, "{:02}", self.day)?,
+                Some('e') => write!(w, "{:2}", self.day)?,
+                Some('F') => self.write("%Y-%m-%d", w)?,
+                Some('H') => write!(w, "{:02}", self.hour)?,
+                Some('I') => write!(w, "{:02}", (self.hour + 11) % 12 + 1)?,
+                Some('j') => write!(w, "{:03}", self.yday)?,
+                Some('m') => write!(w, "{:02}", self.month)?,
+                Some('M') => write!(w, "{:02}", self.minute)?,
+                Some('n') => w.write_char('\n')?,
+                Some('p') => {
+                    w.write_str(if self.hour < 12 { "AM" } else { "PM" })?
+                }
+                Some('R') => self.write("%H:%M", w)?,
+                Some('s') => write!(w, "{}", self.to_unix())?,
+                Some('S') => write!(w, "{:02}", self.second)?,
+                Some('t') => w.write_char('\t')?,
+                Some('T') => self.write("%H:%M:%S", w)?,
+                Some('u') => write!(w, "{}", (self.weekday + 6) % 7 + 1)?,
+                Some('w') => write!(w, "{}", self.weekday)?,
+                Some('y') => {
+                    write!(w, "{:02}", (self.year % 100 + 100) % 100)?
+                }
+                Some('Y') => write!(w, "{}", self.year)?,
+                Some('z') => {
+                    let sign = if self.offset < 0 { '-' } else { '+' };
+                    let minutes = self.offset.abs() / 60;
+                    write!(w, "{}{:02}{:02}", sign, minutes / 60, minutes % 60)?
+                }
+                Some('%') => w.write_char('%')?,
+                _ => return Err(fmt::Error),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn day_name(&self) -> &'static str {
+        DAY_NAMES[self.weekday as usize]
+    }
+
+    fn month_name(&self) -> &'static str {
+        MONTH_NAMES[self.month as usize - 1]
+    }
+}
+
+/// Daylight saving time configuration of a `Zone`
+#[derive(Clone, Copy, Debug)]
+pub enum Dst {
+    /// DST is never in effect
+    Off,
+    /// DST is always in effect (`Time.beginDST`)
+    On,
+    /// DST is in effect between the transitions of the given rule
+    Rule(DstRule),
+}
+
+/// Yearly DST start and end transitions
+#[derive(Clone, Copy, Debug)]
+pub struct DstRule {
+    /// When DST starts, in local standard time
+    pub start: Transition,
+    /// When DST ends, in local daylight time
+    pub end: Transition,
+}
+
+impl DstRule {
+    /// United States: second Sunday of March to first Sunday of November, at
+    /// 02:00 local time
+    pub const US: DstRule = DstRule {
+        start: Transition {
+            month: 3,
+            week: 2,
+            weekday: 0,
+            hour: 2,
+        },
+        end: Transition {
+            month: 11,
+            week: 1,
+            weekday: 0,
+            hour: 2,
+        },
+    };
+
+    /// Central European Time: last Sunday of March to last Sunday of
+    /// October, at 01:00 UTC
+    pub const CENTRAL_EUROPE: DstRule = DstRule {
+        start: Transition {
+            month: 3,
+            week: 5,
+            weekday: 0,
+            hour: 2,
+        },
+        end: Transition {
+            month: 10,
+            week: 5,
+            weekday: 0,
+            hour: 3,
+        },
+    };
+}
+
+/// A DST transition: the `week`th `weekday` of `month` at `hour`
+///
+/// This mirrors the `Mm.w.d/h` format of the POSIX `TZ` variable.
+#[derive(Clone, Copy, Debug)]
+pub struct Transition {
+    /// Month of the year, `1..13`
+    pub month: u8,
+    /// Week of the month, `1..6` where `5` means the last week
+    pub week: u8,
+    /// Day of the week, `0..7` where `0` is Sunday
+    pub weekday: u8,
+    /// Hour of the day, in local time
+    pub hour: u8,
+}
+
+impl Transition {
+    /// Returns the local time, in seconds since the epoch, at which this
+    /// transition happens in `year`
+    fn local_time(&self, year: i32) -> i64 {
+        let first = days_from_civil(year, self.month, 1);
+        let mut day = 1 + (i64::from(self.weekday) + 7 -
+                               i64::from(weekday(first))) % 7 +
+            7 * (i64::from(self.week) - 1);
+        while day > i64::from(days_in_month(year, self.month)) {
+            day -= 7;
+        }
+
+        (first + day - 1) * SECONDS_PER_DAY + i64::from(self.hour) * 3600
+    }
+}
+
+/// A time zone with a fixed offset from UTC and optional DST
+#[derive(Clone, Copy, Debug)]
+pub struct Zone {
+    /// Offset from UTC in seconds, in standard time
+    pub offset: i32,
+    /// Additional offset in seconds while DST is in effect
+    pub dst_offset: i32,
+    /// When DST is in effect
+    pub dst: Dst,
+}
+
+impl Zone {
+    /// Coordinated Universal Time
+    pub const UTC: Zone = Zone {
+        offset: 0,
+        dst_offset: 0,
+        dst: Dst::Off,
+    };
+
+    /// A zone with a fixed offset of `hours` from UTC (`Time.zone`) and no
+    /// DST
+    ///
+    /// Returns an error if `hours` is not in the `-12.0...14.0` range
+    pub fn fixed(hours: f32) -> Result<Self, ()> {
+        if !(-12. ..=14.).contains(&hours) {
+            return Err(());
+        }
+
+        Ok(Zone {
+            offset: (hours * 3600.) as i32,
+            // `Time.setDSTOffset` default
+            dst_offset: 3600,
+            dst: Dst::Off,
+        })
+    }
+
+    /// Returns `true` if DST is in effect at Unix time `t`
+    pub fn is_dst(&self, t: i64) -> bool {
+        match self.dst {
+            Dst::Off => false,
+            Dst::On => true,
+            Dst::Rule(ref rule) => {
+                let local = t + i64::from(self.offset);
+                let year = DateTime::from_unix(local).year;
+                let start = rule.start.local_time(year);
+                let end = rule.end.local_time(year) -
+                    i64::from(self.dst_offset);
+
+                if start < end {
+                    local >= start && local < end
+                } else {
+                    // southern hemisphere
+                    local >= start || local < end
+                }
+            }
+        }
+    }
+
+    /// Breaks down Unix time `t` into the local date and time of this zone
+    pub fn local(&self, t: i64) -> DateTime {
+        let dst = self.is_dst(t);
+        let offset = if dst {
+            self.offset + self.dst_offset
+        } else {
+            self.offset
+        };
+
+        DateTime::from_unix_with_offset(t, offset, dst)
+    }
+
+    /// Returns the current local date and time of this zone
+    pub fn now(&self) -> DateTime {
+        self.local(now())
+    }
+}
+
+struct Cursor<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Write for Cursor<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buffer.len() {
+            return Err(fmt::Error);
+        }
+
+        self.buffer[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+fn div_floor(a: i64, b: i64) -> i64 {
+    let q = a / b;
+    if a % b < 0 { q - 1 } else { q }
+}
+
+fn is_leap_year(year: i32) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        2 => if is_leap_year(year) { 29 } else { 28 },
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Day of the week of the given days since the epoch; `0` is Sunday
+fn weekday(days: i64) -> u8 {
+    // 1970-01-01 was a Thursday
+    ((days + 4) % 7 + 7) as u8 % 7
+}
+
+// Algorithms from http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i32, month: u8, day: u8) -> i64 {
+    let y = i64::from(if month <= 2 { year - 1 } else { year });
+    let m = i64::from(month);
+    let era = div_floor(y, 400);
+    let yoe = y - era * 400;
+    let mp = if m > 2 { m - 3 } else { m + 9 };
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    let z = days + 719468;
+    let era = div_floor(z, 146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year as i32, month as u8, day as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EST: Zone = Zone {
+        offset: -5 * 3600,
+        dst_offset: 3600,
+        dst: Dst::Rule(DstRule::US),
+    };
+
+    const CET: Zone = Zone {
+        offset: 3600,
+        dst_offset: 3600,
+        dst: Dst::Rule(DstRule::CENTRAL_EUROPE),
+    };
+
+    #[test]
+    fn from_unix() {
+        let dt = DateTime::from_unix(1400634527);
+
+        assert_eq!(
+            (dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second),
+            (2014, 5, 21, 1, 8, 47)
+        );
+        assert_eq!(dt.weekday, 3);
+        assert_eq!(dt.yday, 141);
+        assert_eq!(dt.to_unix(), 1400634527);
+    }
+
+    #[test]
+    fn before_the_epoch() {
+        let dt = DateTime::from_unix(-1);
+
+        assert_eq!(
+            (dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second),
+            (1969, 12, 31, 23, 59, 59)
+        );
+        assert_eq!(dt.weekday, 3);
+        assert_eq!(dt.to_unix(), -1);
+    }
+
+    #[test]
+    fn leap_year() {
+        let dt = DateTime::from_unix(1483185600);
+
+        assert_eq!((dt.year, dt.month, dt.day), (2016, 12, 31));
+        assert_eq!(dt.yday, 366);
+    }
+
+    #[test]
+    fn us_transitions() {
+        // 2017-03-12 07:00 UTC, 02:00 EST
+        assert!(!EST.is_dst(1489302000 - 1));
+        assert!(EST.is_dst(1489302000));
+        assert_eq!(EST.local(1489302000).hour, 3);
+
+        // 2017-11-05 06:00 UTC, 02:00 EDT
+        assert!(EST.is_dst(1509861600 - 1));
+        assert!(!EST.is_dst(1509861600));
+        assert_eq!(EST.local(1509861600).hour, 1);
+        assert_eq!(EST.local(1509861600).offset, -5 * 3600);
+    }
+
+    #[test]
+    fn central_europe_transitions() {
+        // 2017-03-26 01:00 UTC
+        assert!(!CET.is_dst(1490490000 - 1));
+        assert!(CET.is_dst(1490490000));
+
+        // 2017-10-29 01:00 UTC
+        assert!(CET.is_dst(1509238800 - 1));
+        assert!(!CET.is_dst(1509238800));
+    }
+
+    #[test]
+    fn format() {
+        let mut buffer = [0; 64];
+        let dt = DateTime::from_unix(1400634527);
+
+        assert_eq!(
+            dt.format(FORMAT_DEFAULT, &mut buffer),
+            Ok("Wed May 21 01:08:47 2014")
+        );
+        assert_eq!(
+            EST.local(1400634527).format(FORMAT_ISO8601_FULL, &mut buffer),
+            Ok("2014-05-20T21:08:47-0400")
+        );
+        assert_eq!(
+            dt.format("%A %e %B %I%p %j %u %y %%", &mut buffer),
+            Ok("Wednesday 21 May 01AM 141 3 14 %")
+        );
+        assert_eq!(dt.format("%s", &mut buffer), Ok("1400634527"));
+    }
+
+    #[test]
+    fn format_errors() {
+        let dt = DateTime::from_unix(0);
+
+        assert_eq!(dt.format("%Y", &mut [0; 3]), Err(()));
+        assert_eq!(dt.format("%Q", &mut [0; 16]), Err(()));
+
+        // out of range fields
+        let mut buffer = [0; 64];
+        for &dt in &[
+            DateTime { month: 0, ..dt },
+            DateTime { month: 13, ..dt },
+            DateTime { weekday: 7, ..dt },
+            DateTime { hour: 255, ..dt },
+        ] {
+            assert_eq!(dt.format(FORMAT_DEFAULT, &mut buffer), Err(()));
+            assert_eq!(dt.format("%I", &mut buffer), Err(()));
+        }
+    }
+
+    #[test]
+    fn fixed_zone() {
+        assert_eq!(Zone::fixed(5.5).map(|zone| zone.offset), Ok(19800));
+        assert!(Zone::fixed(-12.5).is_err());
+        assert!(Zone::fixed(14.5).is_err());
+    }
+}
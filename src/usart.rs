@@ -0,0 +1,494 @@
+//! Hardware USART
+
+use core::cell::UnsafeCell;
+use core::convert::Infallible;
+use core::{cmp, fmt, ptr};
+
+use ll::{self, Ring_Buffer, SERIAL_BUFFER_SIZE};
+use {hal, io, nb};
+
+/// Number of data bits per frame
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DataBits {
+    Seven,
+    Eight,
+    Nine,
+}
+
+/// Parity bit
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Number of stop bits per frame
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StopBits {
+    Half,
+    One,
+    OneAndHalf,
+    Two,
+}
+
+/// LIN mode
+///
+/// Break detection only works in LIN slave mode
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Lin {
+    /// Sends 13-bit breaks
+    Master,
+    /// Detects 10-bit breaks
+    Slave10,
+    /// Detects 11-bit breaks
+    Slave11,
+}
+
+/// Frame format of the serial port
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Config {
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub lin: Option<Lin>,
+}
+
+impl Config {
+    fn bits(&self) -> u32 {
+        let data_bits = match self.data_bits {
+            DataBits::Seven => ll::SERIAL_DATA_BITS_7,
+            DataBits::Eight => ll::SERIAL_DATA_BITS_8,
+            DataBits::Nine => ll::SERIAL_DATA_BITS_9,
+        };
+
+        let parity = match self.parity {
+            Parity::None => ll::SERIAL_PARITY_NO,
+            Parity::Even => ll::SERIAL_PARITY_EVEN,
+            Parity::Odd => ll::SERIAL_PARITY_ODD,
+        };
+
+        let stop_bits = match self.stop_bits {
+            StopBits::Half => ll::SERIAL_STOP_BITS_0_5,
+            StopBits::One => ll::SERIAL_STOP_BITS_1,
+            StopBits::OneAndHalf => ll::SERIAL_STOP_BITS_1_5,
+            StopBits::Two => ll::SERIAL_STOP_BITS_2,
+        };
+
+        let lin = match self.lin {
+            None => 0,
+            Some(Lin::Master) => ll::LIN_MODE_MASTER | ll::LIN_BREAK_13B,
+            Some(Lin::Slave10) => ll::LIN_MODE_SLAVE | ll::LIN_BREAK_10B,
+            Some(Lin::Slave11) => ll::LIN_MODE_SLAVE | ll::LIN_BREAK_11B,
+        };
+
+        data_bits | parity | stop_bits | lin
+    }
+}
+
+impl Default for Config {
+    /// 8 data bits, no parity, 1 stop bit
+    fn default() -> Self {
+        Config {
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            lin: None,
+        }
+    }
+}
+
+/// Ring buffer shared with the USART interrupt handler
+struct Buffer(UnsafeCell<Ring_Buffer>);
+
+// the HAL is the only one that accesses the buffer contents
+unsafe impl Sync for Buffer {}
+
+impl Buffer {
+    const fn new() -> Self {
+        Buffer(UnsafeCell::new(Ring_Buffer {
+            buffer: [0; SERIAL_BUFFER_SIZE],
+            head: 0,
+            tail: 0,
+        }))
+    }
+}
+
+macro_rules! usart {
+    ($(#[$attr:meta])* $Serial:ident, $serial:ident, $RX:ident, $TX:ident) => {
+        static $RX: Buffer = Buffer::new();
+        static $TX: Buffer = Buffer::new();
+
+        $(#[$attr])*
+        pub struct $Serial;
+
+        impl $Serial {
+            /// Enables the serial port with the specified `baud` rate and
+            /// the default frame format (8N1)
+            pub fn begin(&self, baud: u32) {
+                self.begin_config(baud, Config::default())
+            }
+
+            /// Enables the serial port with the specified `baud` rate and
+            /// frame format
+            ///
+            /// This resets the receive and transmit buffers
+            pub fn begin_config(&self, baud: u32, config: Config) {
+                unsafe {
+                    ll::HAL_USART_Init(
+                        ll::HAL_USART_Serial::$serial,
+                        $RX.0.get(),
+                        $TX.0.get(),
+                    );
+                    ll::HAL_USART_BeginConfig(
+                        ll::HAL_USART_Serial::$serial,
+                        baud,
+                        config.bits(),
+                        ptr::null_mut(),
+                    );
+                }
+            }
+
+            /// Disables the serial port
+            pub fn end(&self) {
+                unsafe { ll::HAL_USART_End(ll::HAL_USART_Serial::$serial) }
+            }
+
+            /// Returns `true` if the serial port is enabled
+            pub fn is_enabled(&self) -> bool {
+                unsafe {
+                    ll::HAL_USART_Is_Enabled(ll::HAL_USART_Serial::$serial)
+                }
+            }
+
+            /// Returns the number of bytes available for reading
+            pub fn available(&self) -> usize {
+                match unsafe {
+                    ll::HAL_USART_Available_Data(ll::HAL_USART_Serial::$serial)
+                } {
+                    n if n > 0 => n as usize,
+                    _ => 0,
+                }
+            }
+
+            /// Returns the number of bytes that can be written without
+            /// blocking
+            pub fn available_for_write(&self) -> usize {
+                match unsafe {
+                    ll::HAL_USART_Available_Data_For_Write(
+                        ll::HAL_USART_Serial::$serial,
+                    )
+                } {
+                    n if n > 0 => n as usize,
+                    _ => 0,
+                }
+            }
+
+            /// Reads one byte from the serial port
+            ///
+            /// Returns `None` if no data is available. In 9-bit mode the 9th
+            /// bit is dropped; use `read_nine_bits` to see it
+            pub fn read(&self) -> Option<u8> {
+                self.read_nine_bits().map(|word| word as u8)
+            }
+
+            /// Returns the next byte that `read` will return without
+            /// removing it from the receive buffer
+            pub fn peek(&self) -> Option<u8> {
+                self.peek_nine_bits().map(|word| word as u8)
+            }
+
+            /// Reads one 9-bit word from the serial port
+            ///
+            /// The 9th bit, e.g. the address mark of a multidrop bus, is bit
+            /// 8 of the word. Returns `None` if no data is available
+            pub fn read_nine_bits(&self) -> Option<u16> {
+                match unsafe {
+                    ll::HAL_USART_Read_Data(ll::HAL_USART_Serial::$serial)
+                } {
+                    -1 => None,
+                    word => Some(word as u16),
+                }
+            }
+
+            /// Returns the next word that `read_nine_bits` will return
+            /// without removing it from the receive buffer
+            pub fn peek_nine_bits(&self) -> Option<u16> {
+                match unsafe {
+                    ll::HAL_USART_Peek_Data(ll::HAL_USART_Serial::$serial)
+                } {
+                    -1 => None,
+                    word => Some(word as u16),
+                }
+            }
+
+            /// Reads the available bytes into `buffer`
+            ///
+            /// This doesn't block. Returns the number of bytes read
+            pub fn read_bytes(&self, buffer: &mut [u8]) -> usize {
+                let mut n = 0;
+                for slot in buffer {
+                    match self.read() {
+                        Some(byte) => *slot = byte,
+                        None => break,
+                    }
+                    n += 1;
+                }
+                n
+            }
+
+            /// Queues one byte for transmission
+            ///
+            /// This doesn't block; returns an error if the transmit buffer
+            /// is full
+            pub fn write(&self, byte: u8) -> Result<(), ()> {
+                if self.available_for_write() == 0 {
+                    return Err(());
+                }
+
+                unsafe {
+                    ll::HAL_USART_Write_Data(
+                        ll::HAL_USART_Serial::$serial,
+                        byte,
+                    );
+                }
+
+                Ok(())
+            }
+
+            /// Writes all the `bytes` to the serial port
+            ///
+            /// This blocks while the transmit buffer is full
+            pub fn write_all(&self, bytes: &[u8]) {
+                for &byte in bytes {
+                    while self.write(byte).is_err() {}
+                }
+            }
+
+            /// Queues one 9-bit word for transmission
+            ///
+            /// The port must have been configured with `DataBits::Nine`.
+            /// This doesn't block; returns an error if the transmit buffer
+            /// is full
+            pub fn write_nine_bits(&self, word: u16) -> Result<(), ()> {
+                if self.available_for_write() == 0 {
+                    return Err(());
+                }
+
+                unsafe {
+                    ll::HAL_USART_Write_NineBitData(
+                        ll::HAL_USART_Serial::$serial,
+                        word,
+                    );
+                }
+
+                Ok(())
+            }
+
+            /// Enables or disables single wire half duplex mode
+            ///
+            /// In half duplex mode the TX pin is used both to transmit and
+            /// receive; it must be pulled up externally
+            pub fn half_duplex(&self, enable: bool) {
+                unsafe {
+                    ll::HAL_USART_Half_Duplex(
+                        ll::HAL_USART_Serial::$serial,
+                        enable,
+                    )
+                }
+            }
+
+            /// Sends a break character
+            ///
+            /// The break length depends on the LIN mode; without LIN mode
+            /// it's one frame long
+            pub fn send_break(&self) {
+                unsafe {
+                    ll::HAL_USART_Send_Break(
+                        ll::HAL_USART_Serial::$serial,
+                        ptr::null_mut(),
+                    )
+                }
+            }
+
+            /// Returns `true` if a break was received since the last call
+            ///
+            /// Only works in LIN slave mode
+            pub fn break_detected(&self) -> bool {
+                unsafe {
+                    ll::HAL_USART_Break_Detected(
+                        ll::HAL_USART_Serial::$serial,
+                    ) != 0
+                }
+            }
+
+            /// Waits until all the outgoing data has been transmitted
+            pub fn flush(&self) {
+                unsafe {
+                    ll::HAL_USART_Flush_Data(ll::HAL_USART_Serial::$serial)
+                }
+            }
+        }
+
+        impl fmt::Write for $Serial {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                (&*self).write_str(s)
+            }
+        }
+
+        impl<'a> fmt::Write for &'a $Serial {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.write_all(s.as_bytes());
+                Ok(())
+            }
+        }
+
+        impl hal::serial::Read<u8> for $Serial {
+            type Error = Infallible;
+
+            fn read(&mut self) -> nb::Result<u8, Infallible> {
+                $Serial::read(self).ok_or(nb::Error::WouldBlock)
+            }
+        }
+
+        impl hal::serial::Write<u8> for $Serial {
+            type Error = Infallible;
+
+            fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+                $Serial::write(self, byte).map_err(|_| nb::Error::WouldBlock)
+            }
+
+            fn flush(&mut self) -> nb::Result<(), Infallible> {
+                $Serial::flush(self);
+                Ok(())
+            }
+        }
+
+        impl io::ErrorType for $Serial {
+            type Error = Infallible;
+        }
+
+        /// # Panics
+        ///
+        /// If the port hasn't been enabled with `begin`
+        impl io::Read for $Serial {
+            fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Infallible> {
+                if buffer.is_empty() {
+                    return Ok(0);
+                }
+
+                assert!(self.is_enabled(), "the serial port is disabled");
+
+                loop {
+                    match self.read_bytes(buffer) {
+                        0 => {}
+                        n => return Ok(n),
+                    }
+                }
+            }
+        }
+
+        impl io::ReadReady for $Serial {
+            fn read_ready(&mut self) -> Result<bool, Infallible> {
+                Ok(self.available() != 0)
+            }
+        }
+
+        /// # Panics
+        ///
+        /// If the port hasn't been enabled with `begin`
+        impl io::Write for $Serial {
+            fn write(&mut self, buffer: &[u8]) -> Result<usize, Infallible> {
+                if buffer.is_empty() {
+                    return Ok(0);
+                }
+
+                assert!(self.is_enabled(), "the serial port is disabled");
+
+                loop {
+                    match self.available_for_write() {
+                        0 => {}
+                        n => {
+                            let n = cmp::min(n, buffer.len());
+                            for &byte in &buffer[..n] {
+                                $Serial::write(self, byte).ok();
+                            }
+                            return Ok(n);
+                        }
+                    }
+                }
+            }
+
+            fn flush(&mut self) -> Result<(), Infallible> {
+                $Serial::flush(self);
+                Ok(())
+            }
+        }
+
+        impl io::WriteReady for $Serial {
+            fn write_ready(&mut self) -> Result<bool, Infallible> {
+                Ok(self.available_for_write() != 0)
+            }
+        }
+    }
+}
+
+usart!(
+    /// Hardware serial port on the TX and RX pins
+    Serial1,
+    HAL_USART_SERIAL1,
+    SERIAL1_RX,
+    SERIAL1_TX
+);
+
+usart!(
+    /// Hardware serial port on the RGB LED pins: green is TX, blue is RX
+    ///
+    /// **NOTE** The RGB LED must be taken over by the application before
+    /// using this port
+    Serial2,
+    HAL_USART_SERIAL2,
+    SERIAL2_RX,
+    SERIAL2_TX
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock;
+
+    #[test]
+    fn address_mark() {
+        let _usart = mock::usart_receive(&[0x41, 0x1AA, 0x42]);
+
+        let mut buffer = [0; 8];
+        assert_eq!(Serial1.read_bytes(&mut buffer), 3);
+        assert_eq!(buffer[..3], [0x41, 0xAA, 0x42]);
+        assert_eq!(Serial1.read(), None);
+    }
+
+    #[test]
+    fn address_mark_io() {
+        let _usart = mock::usart_receive(&[0x41, 0x1AA, 0x42]);
+
+        let mut buffer = [0; 8];
+        assert_eq!(io::Read::read(&mut Serial1, &mut buffer), Ok(3));
+        assert_eq!(buffer[..3], [0x41, 0xAA, 0x42]);
+        assert_eq!(io::ReadReady::read_ready(&mut Serial1), Ok(false));
+    }
+
+    #[test]
+    fn nine_bit_reads() {
+        let _usart = mock::usart_receive(&[0x41, 0x1AA, 0x42]);
+
+        assert_eq!(Serial1.read(), Some(0x41));
+        assert_eq!(Serial1.peek_nine_bits(), Some(0x1AA));
+        assert_eq!(Serial1.peek(), Some(0xAA));
+        assert_eq!(Serial1.read_nine_bits(), Some(0x1AA));
+        assert_eq!(
+            hal::serial::Read::read(&mut Serial1),
+            Ok::<_, nb::Error<Infallible>>(0x42)
+        );
+        assert_eq!(Serial1.read_nine_bits(), None);
+    }
+}
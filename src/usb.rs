@@ -0,0 +1,773 @@
+//! USB device control, HID and control requests
+//!
+//! With HID enabled the Photon enumerates as a composite device: the USB
+//! serial port plus a keyboard and a mouse.
+//!
+//! ``` ignore
+//! usb::hid_begin();
+//!
+//! let mut keyboard = Keyboard::new();
+//! write!(keyboard, "hello\n").ok();
+//!
+//! Mouse::new().move_by(10, -10);
+//! ```
+//!
+//! Host tools can talk to the application through control requests, without
+//! going through the USB serial port:
+//!
+//! ``` ignore
+//! fn on_request(request: &[u8], reply: &mut Reply) -> Result<(), ()> {
+//!     match request {
+//!         b"version" => reply.write(b"1.2.0"),
+//!         _ => Err(()),
+//!     }
+//! }
+//!
+//! usb::set_request_handler(on_request);
+//! ```
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{cmp, fmt, mem, ops, ptr, slice};
+
+use ll::{self, USB_REQUEST_BUFFER_SIZE};
+
+/// How long to stay detached, in milliseconds, when re-enumerating
+const REENUMERATE_DELAY_MS: u32 = 100;
+
+/// Report ID of the mouse reports
+const MOUSE_REPORT_ID: u8 = 0x01;
+
+/// Report ID of the keyboard reports
+const KEYBOARD_REPORT_ID: u8 = 0x02;
+
+/// How long to wait, in milliseconds, for the previous report to be sent
+const REPORT_TIMEOUT_MS: u32 = 50;
+
+/// USB device controller
+pub struct Usb;
+
+impl Usb {
+    /// Initializes the USB peripheral
+    ///
+    /// The system does this at boot; it's only needed after `detach` if the
+    /// peripheral was powered down by other means, e.g. sleep modes
+    pub fn init(&self) {
+        unsafe { ll::HAL_USB_Init() }
+    }
+
+    /// Connects to the host, which then enumerates the device
+    pub fn attach(&self) {
+        unsafe { ll::HAL_USB_Attach() }
+    }
+
+    /// Disconnects from the host
+    ///
+    /// The USB serial ports, HID and control requests stop working until
+    /// `attach` is called
+    pub fn detach(&self) {
+        unsafe { ll::HAL_USB_Detach() }
+    }
+
+    /// Disconnects and reconnects, which forces the host to enumerate the
+    /// device again
+    ///
+    /// This isn't needed after `hid_begin`, which re-enumerates the device
+    /// itself
+    pub fn reenumerate(&self) {
+        self.detach();
+        ::delay_ms(REENUMERATE_DELAY_MS);
+        self.attach();
+    }
+}
+
+/// Enables the HID keyboard and mouse
+///
+/// The device re-enumerates so the host picks up the new interfaces
+pub fn hid_begin() {
+    unsafe {
+        ll::HAL_USB_HID_Init(0, ptr::null_mut());
+        ll::HAL_USB_HID_Begin(0, ptr::null_mut());
+    }
+}
+
+/// Disables the HID keyboard and mouse
+pub fn hid_end() {
+    unsafe { ll::HAL_USB_HID_End(0) }
+}
+
+/// Returns `true` while the last report is still being sent
+pub fn hid_is_busy() -> bool {
+    unsafe { ll::HAL_USB_HID_Status(0, ptr::null_mut()) != 0 }
+}
+
+fn send_report(report: &mut [u8]) {
+    let start = ::millis();
+    while hid_is_busy() &&
+        ::millis().wrapping_sub(start) < REPORT_TIMEOUT_MS
+    {}
+
+    unsafe {
+        ll::HAL_USB_HID_Send_Report(
+            0,
+            report.as_mut_ptr() as *mut ll::c_void,
+            report.len() as u16,
+            ptr::null_mut(),
+        )
+    }
+}
+
+/// Keyboard modifier keys
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Modifiers(pub u8);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const LEFT_CTRL: Modifiers = Modifiers(1 << 0);
+    pub const LEFT_SHIFT: Modifiers = Modifiers(1 << 1);
+    pub const LEFT_ALT: Modifiers = Modifiers(1 << 2);
+    pub const LEFT_GUI: Modifiers = Modifiers(1 << 3);
+    pub const RIGHT_CTRL: Modifiers = Modifiers(1 << 4);
+    pub const RIGHT_SHIFT: Modifiers = Modifiers(1 << 5);
+    pub const RIGHT_ALT: Modifiers = Modifiers(1 << 6);
+    pub const RIGHT_GUI: Modifiers = Modifiers(1 << 7);
+}
+
+impl ops::BitOr for Modifiers {
+    type Output = Modifiers;
+
+    fn bitor(self, rhs: Modifiers) -> Modifiers {
+        Modifiers(self.0 | rhs.0)
+    }
+}
+
+/// Keyboard key, as a HID usage ID
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Key(pub u8);
+
+impl Key {
+    pub const ENTER: Key = Key(0x28);
+    pub const ESCAPE: Key = Key(0x29);
+    pub const BACKSPACE: Key = Key(0x2A);
+    pub const TAB: Key = Key(0x2B);
+    pub const SPACE: Key = Key(0x2C);
+    pub const CAPS_LOCK: Key = Key(0x39);
+    pub const F1: Key = Key(0x3A);
+    pub const F2: Key = Key(0x3B);
+    pub const F3: Key = Key(0x3C);
+    pub const F4: Key = Key(0x3D);
+    pub const F5: Key = Key(0x3E);
+    pub const F6: Key = Key(0x3F);
+    pub const F7: Key = Key(0x40);
+    pub const F8: Key = Key(0x41);
+    pub const F9: Key = Key(0x42);
+    pub const F10: Key = Key(0x43);
+    pub const F11: Key = Key(0x44);
+    pub const F12: Key = Key(0x45);
+    pub const PRINT_SCREEN: Key = Key(0x46);
+    pub const INSERT: Key = Key(0x49);
+    pub const HOME: Key = Key(0x4A);
+    pub const PAGE_UP: Key = Key(0x4B);
+    pub const DELETE: Key = Key(0x4C);
+    pub const END: Key = Key(0x4D);
+    pub const PAGE_DOWN: Key = Key(0x4E);
+    pub const RIGHT: Key = Key(0x4F);
+    pub const LEFT: Key = Key(0x50);
+    pub const DOWN: Key = Key(0x51);
+    pub const UP: Key = Key(0x52);
+
+    /// Returns the key, and the modifiers, that type the ASCII character `c`
+    /// on a US keyboard layout
+    pub fn from_ascii(c: u8) -> Option<(Modifiers, Key)> {
+        const SHIFTED: &[u8; 21] = b"!@#$%^&*()_+{}|:\"~<>?";
+        const UNSHIFTED: &[u8; 21] = b"1234567890-=[]\\;'`,./";
+
+        let (modifiers, c) = match c {
+            b'A'..=b'Z' => (Modifiers::LEFT_SHIFT, c - b'A' + b'a'),
+            _ => match SHIFTED.iter().position(|&s| s == c) {
+                Some(i) => (Modifiers::LEFT_SHIFT, UNSHIFTED[i]),
+                None => (Modifiers::NONE, c),
+            },
+        };
+
+        let key = match c {
+            b'a'..=b'z' => Key(0x04 + c - b'a'),
+            b'1'..=b'9' => Key(0x1E + c - b'1'),
+            b'0' => Key(0x27),
+            b'\n' => Key::ENTER,
+            0x1B => Key::ESCAPE,
+            0x08 => Key::BACKSPACE,
+            b'\t' => Key::TAB,
+            b' ' => Key::SPACE,
+            b'-' => Key(0x2D),
+            b'=' => Key(0x2E),
+            b'[' => Key(0x2F),
+            b']' => Key(0x30),
+            b'\\' => Key(0x31),
+            b';' => Key(0x33),
+            b'\'' => Key(0x34),
+            b'`' => Key(0x35),
+            b',' => Key(0x36),
+            b'.' => Key(0x37),
+            b'/' => Key(0x38),
+            _ => return None,
+        };
+
+        Some((modifiers, key))
+    }
+}
+
+/// Keyboard report builder
+///
+/// Holds the modifiers and up to 6 keys that are pressed
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct KeyboardReport {
+    modifiers: Modifiers,
+    keys: [u8; 6],
+}
+
+impl KeyboardReport {
+    /// A report with no keys pressed
+    pub fn new() -> Self {
+        KeyboardReport::default()
+    }
+
+    /// Adds `modifiers` to the report
+    pub fn modifiers(mut self, modifiers: Modifiers) -> Self {
+        self.modifiers = self.modifiers | modifiers;
+        self
+    }
+
+    /// Adds `key` to the report; ignored if the report already has 6 keys
+    pub fn key(mut self, key: Key) -> Self {
+        self.press(key).ok();
+        self
+    }
+
+    /// Sends the report to the host
+    pub fn send(&self) {
+        send_report(&mut self.bytes());
+    }
+
+    fn bytes(&self) -> [u8; 9] {
+        let mut report = [0; 9];
+        report[0] = KEYBOARD_REPORT_ID;
+        report[1] = self.modifiers.0;
+        report[3..].copy_from_slice(&self.keys);
+        report
+    }
+
+    fn press(&mut self, key: Key) -> Result<(), ()> {
+        if self.keys.contains(&key.0) {
+            return Ok(());
+        }
+
+        let slot = self.keys.iter_mut().find(|k| **k == 0).ok_or(())?;
+        *slot = key.0;
+        Ok(())
+    }
+
+    fn release(&mut self, key: Key) {
+        for k in self.keys.iter_mut().filter(|k| **k == key.0) {
+            *k = 0;
+        }
+    }
+}
+
+/// HID keyboard
+///
+/// Tracks the pressed keys and sends a report on every change. The
+/// `fmt::Write` implementation types text using a US layout; characters
+/// that can't be typed are skipped.
+pub struct Keyboard {
+    report: KeyboardReport,
+}
+
+impl Keyboard {
+    /// Creates a keyboard with no keys pressed
+    pub fn new() -> Self {
+        Keyboard {
+            report: KeyboardReport::new(),
+        }
+    }
+
+    /// Presses `key` together with `modifiers`
+    ///
+    /// Returns an error if 6 keys are already pressed
+    pub fn press(&mut self, modifiers: Modifiers, key: Key) -> Result<(), ()> {
+        self.report.press(key)?;
+        self.report.modifiers = self.report.modifiers | modifiers;
+        self.report.send();
+        Ok(())
+    }
+
+    /// Releases `key`; the modifiers are kept
+    pub fn release(&mut self, key: Key) {
+        self.report.release(key);
+        self.report.send();
+    }
+
+    /// Releases all the keys and modifiers
+    pub fn release_all(&mut self) {
+        self.report = KeyboardReport::new();
+        self.report.send();
+    }
+
+    /// Presses and releases `key` together with `modifiers`
+    ///
+    /// The keys and modifiers that are held stay pressed. `key` is ignored
+    /// if 6 keys are already pressed
+    pub fn click(&mut self, modifiers: Modifiers, key: Key) {
+        self.report.modifiers(modifiers).key(key).send();
+        self.report.send();
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Keyboard::new()
+    }
+}
+
+impl fmt::Write for Keyboard {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if let Some((modifiers, key)) = Key::from_ascii(byte) {
+                self.click(modifiers, key);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Mouse buttons
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Buttons(pub u8);
+
+impl Buttons {
+    pub const NONE: Buttons = Buttons(0);
+    pub const LEFT: Buttons = Buttons(1 << 0);
+    pub const RIGHT: Buttons = Buttons(1 << 1);
+    pub const MIDDLE: Buttons = Buttons(1 << 2);
+}
+
+impl ops::BitOr for Buttons {
+    type Output = Buttons;
+
+    fn bitor(self, rhs: Buttons) -> Buttons {
+        Buttons(self.0 | rhs.0)
+    }
+}
+
+/// Mouse report builder
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MouseReport {
+    buttons: Buttons,
+    x: i16,
+    y: i16,
+    wheel: i8,
+}
+
+impl MouseReport {
+    /// A report with no buttons pressed and no movement
+    pub fn new() -> Self {
+        MouseReport::default()
+    }
+
+    /// Sets the pressed buttons
+    pub fn buttons(mut self, buttons: Buttons) -> Self {
+        self.buttons = buttons;
+        self
+    }
+
+    /// Sets the relative movement
+    pub fn movement(mut self, x: i16, y: i16) -> Self {
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    /// Sets the scroll wheel movement
+    pub fn wheel(mut self, wheel: i8) -> Self {
+        self.wheel = wheel;
+        self
+    }
+
+    /// Sends the report to the host
+    pub fn send(&self) {
+        send_report(&mut self.bytes());
+    }
+
+    fn bytes(&self) -> [u8; 7] {
+        [
+            MOUSE_REPORT_ID,
+            self.buttons.0,
+            self.x as u8,
+            (self.x >> 8) as u8,
+            self.y as u8,
+            (self.y >> 8) as u8,
+            self.wheel as u8,
+        ]
+    }
+}
+
+/// HID mouse
+///
+/// Tracks the pressed buttons and sends a report on every change
+pub struct Mouse {
+    buttons: Buttons,
+}
+
+impl Mouse {
+    /// Creates a mouse with no buttons pressed
+    pub fn new() -> Self {
+        Mouse {
+            buttons: Buttons::NONE,
+        }
+    }
+
+    /// Moves the pointer by `x`, `y`
+    pub fn move_by(&mut self, x: i16, y: i16) {
+        self.report().movement(x, y).send();
+    }
+
+    /// Scrolls the wheel by `wheel` steps
+    pub fn scroll(&mut self, wheel: i8) {
+        self.report().wheel(wheel).send();
+    }
+
+    /// Presses `buttons`
+    pub fn press(&mut self, buttons: Buttons) {
+        self.buttons = self.buttons | buttons;
+        self.report().send();
+    }
+
+    /// Releases `buttons`
+    pub fn release(&mut self, buttons: Buttons) {
+        self.buttons = Buttons(self.buttons.0 & !buttons.0);
+        self.report().send();
+    }
+
+    /// Presses and releases `buttons`
+    pub fn click(&mut self, buttons: Buttons) {
+        self.press(buttons);
+        self.release(buttons);
+    }
+
+    fn report(&self) -> MouseReport {
+        MouseReport::new().buttons(self.buttons)
+    }
+}
+
+impl Default for Mouse {
+    fn default() -> Self {
+        Mouse::new()
+    }
+}
+
+/// Handler of the custom requests, see `set_request_handler`
+pub type RequestHandler =
+    fn(request: &[u8], reply: &mut Reply) -> Result<(), ()>;
+
+/// Handler of the vendor control requests, see `set_vendor_request_handler`
+pub type VendorRequestHandler = fn(request: &mut SetupRequest) -> bool;
+
+/// State shared with the system thread
+struct Shared<T>(UnsafeCell<T>);
+
+// only the system thread accesses the contents, one request at a time
+unsafe impl<T> Sync for Shared<T> {}
+
+/// Address of the `RequestHandler`, `0` if none; it's written by the
+/// application and read by the system thread
+static REQUEST_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Copy of the request data; the system uses the same buffer for the reply
+static REQUEST: Shared<[u8; USB_REQUEST_BUFFER_SIZE]> =
+    Shared(UnsafeCell::new([0; USB_REQUEST_BUFFER_SIZE]));
+
+/// Reply to a custom request
+///
+/// Holds up to `USB_REQUEST_BUFFER_SIZE` bytes
+pub struct Reply<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Reply<'a> {
+    /// Appends `bytes` to the reply
+    ///
+    /// Returns an error, and appends nothing, if `bytes` doesn't fit
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        let end = self.len + bytes.len();
+        if end > self.buffer.len() {
+            return Err(());
+        }
+
+        self.buffer[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Length of the reply so far
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if nothing has been written to the reply
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<'a> fmt::Write for Reply<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Registers `handler` to process the custom control requests sent by the
+/// host
+///
+/// The handler runs in the system thread. Returning an error reports the
+/// request as failed to the host.
+pub fn set_request_handler(handler: RequestHandler) {
+    REQUEST_HANDLER.store(handler as usize, Ordering::Release);
+    unsafe {
+        ll::system_set_usb_request_app_handler(on_request, ptr::null_mut());
+    }
+}
+
+extern "C" fn on_request(
+    req: *mut ll::USBRequest,
+    _: *mut ll::c_void,
+) -> bool {
+    let req = unsafe { &mut *req };
+    let handler = REQUEST_HANDLER.load(Ordering::Acquire);
+    if handler == 0 {
+        return false;
+    }
+    let handler: RequestHandler = unsafe { mem::transmute(handler) };
+
+    if req.ty != ll::USB_REQUEST_CUSTOM {
+        return false;
+    }
+
+    let buffer = unsafe {
+        slice::from_raw_parts_mut(req.data as *mut u8, USB_REQUEST_BUFFER_SIZE)
+    };
+    let request = unsafe { &mut *REQUEST.0.get() };
+    let len = cmp::min(req.request_size, USB_REQUEST_BUFFER_SIZE);
+    request[..len].copy_from_slice(&buffer[..len]);
+
+    let mut reply = Reply {
+        buffer,
+        len: 0,
+    };
+    let result = match handler(&request[..len], &mut reply) {
+        Ok(()) => 0,
+        Err(()) => -1,
+    };
+
+    req.reply_size = reply.len;
+    unsafe { ll::system_set_usb_request_result(req, result, ptr::null_mut()) }
+
+    true
+}
+
+/// A vendor specific control request
+pub struct SetupRequest<'a> {
+    raw: &'a mut ll::HAL_USB_SetupRequest,
+}
+
+impl<'a> SetupRequest<'a> {
+    /// `bmRequestType`
+    pub fn request_type(&self) -> u8 {
+        self.raw.bmRequestType
+    }
+
+    /// `bRequest`
+    pub fn request(&self) -> u8 {
+        self.raw.bRequest
+    }
+
+    /// `wValue`
+    pub fn value(&self) -> u16 {
+        self.raw.wValue
+    }
+
+    /// `wIndex`
+    pub fn index(&self) -> u16 {
+        self.raw.wIndex
+    }
+
+    /// `wLength`
+    pub fn length(&self) -> u16 {
+        self.raw.wLength
+    }
+
+    /// Returns `true` if the host expects data from the device
+    pub fn is_device_to_host(&self) -> bool {
+        self.raw.bmRequestType & 0x80 != 0
+    }
+
+    /// Data stage of the request
+    ///
+    /// For host to device requests this is the data sent by the host. For
+    /// device to host requests this is where the reply goes; it's at most
+    /// 64 bytes long
+    pub fn data(&mut self) -> &mut [u8] {
+        if self.raw.data.is_null() {
+            return &mut [];
+        }
+
+        let len = if self.is_device_to_host() {
+            cmp::min(self.raw.wLength, 64)
+        } else {
+            self.raw.wLength
+        };
+
+        unsafe { slice::from_raw_parts_mut(self.raw.data, usize::from(len)) }
+    }
+
+    /// Sets the length of the reply of a device to host request
+    ///
+    /// Returns an error, and leaves the length untouched, if `len` is longer
+    /// than `data`
+    pub fn set_reply_len(&mut self, len: u16) -> Result<(), ()> {
+        if usize::from(len) > self.data().len() {
+            return Err(());
+        }
+
+        self.raw.wLength = len;
+        Ok(())
+    }
+}
+
+/// Registers `handler` to process the vendor specific control requests
+///
+/// The handler runs in interrupt context and must return quickly. Returning
+/// `false` stalls the request.
+pub fn set_vendor_request_handler(handler: VendorRequestHandler) {
+    unsafe {
+        ll::HAL_USB_Set_Vendor_Request_Callback(
+            on_vendor_request,
+            handler as usize as *mut ll::c_void,
+        )
+    }
+}
+
+extern "C" fn on_vendor_request(
+    req: *mut ll::HAL_USB_SetupRequest,
+    p: *mut ll::c_void,
+) -> u8 {
+    let handler: VendorRequestHandler = unsafe { mem::transmute(p) };
+    let mut request = SetupRequest {
+        raw: unsafe { &mut *req },
+    };
+
+    if handler(&mut request) { 0 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock;
+
+    #[test]
+    fn ascii_keys() {
+        let none = Modifiers::NONE;
+        let shift = Modifiers::LEFT_SHIFT;
+
+        assert_eq!(Key::from_ascii(b'a'), Some((none, Key(0x04))));
+        assert_eq!(Key::from_ascii(b'Z'), Some((shift, Key(0x1D))));
+        assert_eq!(Key::from_ascii(b'1'), Some((none, Key(0x1E))));
+        assert_eq!(Key::from_ascii(b'0'), Some((none, Key(0x27))));
+        assert_eq!(Key::from_ascii(b')'), Some((shift, Key(0x27))));
+        assert_eq!(Key::from_ascii(b'?'), Some((shift, Key(0x38))));
+        assert_eq!(Key::from_ascii(b'"'), Some((shift, Key(0x34))));
+        assert_eq!(Key::from_ascii(b'\n'), Some((none, Key::ENTER)));
+        assert_eq!(Key::from_ascii(b' '), Some((none, Key::SPACE)));
+
+        assert_eq!(Key::from_ascii(b'\r'), None);
+        assert_eq!(Key::from_ascii(0x7F), None);
+        assert_eq!(Key::from_ascii(0xE9), None);
+    }
+
+    #[test]
+    fn keyboard_report() {
+        assert_eq!(KeyboardReport::new().bytes(), [2, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let report = KeyboardReport::new()
+            .modifiers(Modifiers::LEFT_CTRL)
+            .modifiers(Modifiers::RIGHT_ALT)
+            .key(Key(0x04))
+            .key(Key::ENTER)
+            // already pressed
+            .key(Key(0x04));
+        assert_eq!(report.bytes(), [2, 0x41, 0, 0x04, 0x28, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn keyboard_report_rollover() {
+        let mut report = KeyboardReport::new();
+        for key in 0..7 {
+            report = report.key(Key(0x04 + key));
+        }
+        assert_eq!(report.bytes()[3..], [0x04, 0x05, 0x06, 0x07, 0x08, 0x09]);
+        assert_eq!(report.press(Key(0x0A)), Err(()));
+
+        // a released slot is reused
+        report.release(Key(0x05));
+        assert_eq!(report.press(Key(0x0A)), Ok(()));
+        assert_eq!(report.bytes()[3..], [0x04, 0x0A, 0x06, 0x07, 0x08, 0x09]);
+    }
+
+    #[test]
+    fn click_keeps_held_keys() {
+        let _hid = mock::hid_capture();
+
+        let mut keyboard = Keyboard::new();
+        keyboard.press(Modifiers::LEFT_SHIFT, Key(0x04)).unwrap();
+        keyboard.click(Modifiers::LEFT_CTRL, Key(0x05));
+        keyboard.release_all();
+
+        assert_eq!(
+            mock::hid_reports(),
+            [
+                [2, 0x02, 0, 0x04, 0, 0, 0, 0, 0],
+                [2, 0x03, 0, 0x04, 0x05, 0, 0, 0, 0],
+                [2, 0x02, 0, 0x04, 0, 0, 0, 0, 0],
+                [2, 0, 0, 0, 0, 0, 0, 0, 0],
+            ]
+        );
+    }
+
+    #[test]
+    fn mouse_report() {
+        assert_eq!(MouseReport::new().bytes(), [1, 0, 0, 0, 0, 0, 0]);
+
+        let report = MouseReport::new()
+            .buttons(Buttons::LEFT | Buttons::MIDDLE)
+            .movement(-2, 0x0123)
+            .wheel(-1);
+        assert_eq!(report.bytes(), [1, 0x05, 0xFE, 0xFF, 0x23, 0x01, 0xFF]);
+    }
+
+    #[test]
+    fn reply() {
+        let mut buffer = [0; 8];
+        let mut reply = Reply {
+            buffer: &mut buffer,
+            len: 0,
+        };
+        assert!(reply.is_empty());
+
+        assert_eq!(reply.write(b"hello"), Ok(()));
+        assert_eq!(reply.write(b"world"), Err(()));
+        assert_eq!(reply.write(b"!"), Ok(()));
+        assert_eq!(reply.len(), 6);
+        assert_eq!(&buffer[..6], b"hello!");
+    }
+}
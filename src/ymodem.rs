@@ -0,0 +1,395 @@
+//! YMODEM firmware update over serial
+//!
+//! `Receiver` accepts a firmware binary sent with YMODEM (e.g. `sz --ymodem`
+//! or a terminal emulator) and streams it into the OTA region of the flash.
+//! The new firmware is applied on the next reset.
+//!
+//! ``` ignore
+//! // let `particle flash --serial` (listening mode) use this receiver
+//! ymodem::set_serial_handler();
+//! ```
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::{ptr, str};
+
+use hal::serial::{Read, Write};
+use ll::{self, FileTransfer_Descriptor, FileTransfer_Store, Stream};
+use {nb, UsbSerial};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC: u8 = b'C';
+
+/// How long to wait for the sender, in milliseconds, before giving up
+const START_TIMEOUT_MS: u32 = 60_000;
+
+/// How long to wait for the next byte of a packet, in milliseconds
+const BYTE_TIMEOUT_MS: u32 = 1_000;
+
+/// Number of consecutive bad packets tolerated
+const MAX_ERRORS: u32 = 10;
+
+/// YMODEM errors
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The sender stopped responding
+    Timeout,
+    /// The sender cancelled the transfer
+    Cancelled,
+    /// Too many corrupted packets
+    Corrupted,
+    /// The file header is malformed or the file size is missing
+    InvalidHeader,
+    /// The file doesn't fit in the OTA region
+    TooLarge,
+    /// The system refused to start the update
+    Refused,
+    /// Writing to the flash failed
+    Flash,
+    /// The received firmware didn't validate
+    Invalid,
+}
+
+/// Computes the CRC16-CCITT (XMODEM variant) of `data`
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+enum Packet {
+    /// Sequence number and payload length
+    Data(u8, usize),
+    EndOfFile,
+}
+
+/// YMODEM receiver
+pub struct Receiver<S> {
+    serial: S,
+    buffer: [u8; 1024],
+}
+
+impl<S> Receiver<S>
+where
+    S: Read<u8> + Write<u8>,
+{
+    /// Creates a receiver that runs on `serial`
+    pub fn new(serial: S) -> Self {
+        Receiver {
+            serial,
+            buffer: [0; 1024],
+        }
+    }
+
+    /// Releases the serial port
+    pub fn free(self) -> S {
+        self.serial
+    }
+
+    /// Receives a firmware binary and writes it to the OTA region
+    ///
+    /// Only the first file of a batch is kept. Returns the size of the
+    /// firmware; it's applied on the next reset.
+    pub fn receive_firmware(&mut self) -> Result<u32, Error> {
+        let mut file = FileTransfer_Descriptor {
+            file_length: 0,
+            file_address: 0,
+            chunk_address: 0,
+            chunk_size: 0,
+            store: FileTransfer_Store::FIRMWARE,
+        };
+
+        self.receive_into(&mut file)
+    }
+
+    fn receive_into(
+        &mut self,
+        file: &mut FileTransfer_Descriptor,
+    ) -> Result<u32, Error> {
+        let result = self.transfer(file);
+
+        if result.is_err() {
+            // tell the sender to stop
+            for _ in 0..3 {
+                self.send(CAN);
+            }
+        }
+
+        result
+    }
+
+    fn transfer(
+        &mut self,
+        file: &mut FileTransfer_Descriptor,
+    ) -> Result<u32, Error> {
+        // block 0: file name and size
+        let len = self.start()?;
+        let size = self.header(len)?;
+        file.file_length = size;
+
+        if size > unsafe { ll::HAL_OTA_FlashLength() } {
+            return Err(Error::TooLarge);
+        }
+
+        if unsafe {
+            ll::Spark_Prepare_For_Firmware_Update(file, 0, ptr::null_mut())
+        } != 0
+        {
+            return Err(Error::Refused);
+        }
+
+        self.send(ACK);
+        self.send(CRC);
+
+        if let Err(e) = self.data(file, size) {
+            unsafe {
+                ll::Spark_Finish_Firmware_Update(file, 0, ptr::null_mut());
+            }
+            return Err(e);
+        }
+
+        // end of the batch
+        self.send(CRC);
+        if let Ok(Packet::Data(0, _)) = self.packet() {
+            self.send(ACK);
+        }
+
+        if unsafe {
+            ll::Spark_Finish_Firmware_Update(
+                file,
+                ll::UPDATE_FLAG_SUCCESS,
+                ptr::null_mut(),
+            )
+        } != 0
+        {
+            return Err(Error::Invalid);
+        }
+
+        Ok(size)
+    }
+
+    /// Requests the first packet until the sender starts
+    fn start(&mut self) -> Result<usize, Error> {
+        let start = ::millis();
+
+        loop {
+            self.send(CRC);
+
+            match self.packet() {
+                Ok(Packet::Data(0, len)) => return Ok(len),
+                Ok(_) | Err(Error::Corrupted) | Err(Error::Timeout) => {}
+                Err(e) => return Err(e),
+            }
+
+            if ::millis().wrapping_sub(start) > START_TIMEOUT_MS {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+
+    /// Parses the file size out of the block 0 payload
+    fn header(&self, len: usize) -> Result<u32, Error> {
+        let payload = &self.buffer[..len];
+        let name_end = payload
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(Error::InvalidHeader)?;
+
+        if name_end == 0 {
+            // empty batch
+            return Err(Error::InvalidHeader);
+        }
+
+        let info = &payload[name_end + 1..];
+        let size_end = info
+            .iter()
+            .position(|&b| b == b' ' || b == 0)
+            .unwrap_or(info.len());
+
+        str::from_utf8(&info[..size_end])
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .ok_or(Error::InvalidHeader)
+    }
+
+    /// Receives the data packets and writes them to the flash
+    fn data(
+        &mut self,
+        file: &mut FileTransfer_Descriptor,
+        size: u32,
+    ) -> Result<(), Error> {
+        let mut seq = 1u8;
+        let mut offset = 0;
+        let mut errors = 0;
+        let mut eot = false;
+
+        loop {
+            match self.packet() {
+                Ok(Packet::Data(n, len)) if n == seq => {
+                    errors = 0;
+
+                    let len = if offset + len as u32 > size {
+                        (size - offset) as usize
+                    } else {
+                        len
+                    };
+
+                    if len > 0 {
+                        file.chunk_address = file.file_address + offset;
+                        file.chunk_size = len as u16;
+                        if unsafe {
+                            ll::Spark_Save_Firmware_Chunk(
+                                file,
+                                self.buffer.as_ptr(),
+                                ptr::null_mut(),
+                            )
+                        } != 0
+                        {
+                            return Err(Error::Flash);
+                        }
+                        offset += len as u32;
+                    }
+
+                    seq = seq.wrapping_add(1);
+                    self.send(ACK);
+                }
+                // our ACK got lost; the sender is repeating the last packet
+                Ok(Packet::Data(n, _)) if n == seq.wrapping_sub(1) => {
+                    self.send(ACK);
+                }
+                Ok(Packet::Data(..)) => return Err(Error::Corrupted),
+                Ok(Packet::EndOfFile) => {
+                    // NAK the first EOT to make sure it's not line noise
+                    if eot {
+                        self.send(ACK);
+                        return if offset == size {
+                            Ok(())
+                        } else {
+                            Err(Error::Corrupted)
+                        };
+                    }
+
+                    eot = true;
+                    self.send(NAK);
+                }
+                Err(Error::Corrupted) | Err(Error::Timeout) => {
+                    errors += 1;
+                    if errors > MAX_ERRORS {
+                        return Err(Error::Corrupted);
+                    }
+
+                    self.purge();
+                    self.send(NAK);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Receives one packet into `buffer`
+    fn packet(&mut self) -> Result<Packet, Error> {
+        let len = match self.recv()? {
+            SOH => 128,
+            STX => 1024,
+            EOT => return Ok(Packet::EndOfFile),
+            CAN => {
+                return if self.recv() == Ok(CAN) {
+                    Err(Error::Cancelled)
+                } else {
+                    Err(Error::Corrupted)
+                };
+            }
+            _ => return Err(Error::Corrupted),
+        };
+
+        let seq = self.recv()?;
+        let nseq = self.recv()?;
+
+        for i in 0..len {
+            self.buffer[i] = self.recv()?;
+        }
+
+        let crc = u16::from(self.recv()?) << 8 | u16::from(self.recv()?);
+
+        if seq != !nseq || crc16(&self.buffer[..len]) != crc {
+            return Err(Error::Corrupted);
+        }
+
+        Ok(Packet::Data(seq, len))
+    }
+
+    /// Receives one byte
+    fn recv(&mut self) -> Result<u8, Error> {
+        let start = ::millis();
+
+        loop {
+            match self.serial.read() {
+                Ok(byte) => return Ok(byte),
+                Err(nb::Error::WouldBlock) => {
+                    if ::millis().wrapping_sub(start) > BYTE_TIMEOUT_MS {
+                        return Err(Error::Timeout);
+                    }
+                }
+                Err(nb::Error::Other(_)) => return Err(Error::Corrupted),
+            }
+        }
+    }
+
+    /// Discards input until the line goes quiet
+    fn purge(&mut self) {
+        while self.recv().is_ok() {}
+    }
+
+    fn send(&mut self, byte: u8) {
+        nb::block!(self.serial.write(byte)).ok();
+        nb::block!(self.serial.flush()).ok();
+    }
+}
+
+/// Makes serial firmware updates in listening mode use `Receiver` over the
+/// USB serial port instead of the system implementation
+///
+/// The system doesn't identify the port the update was requested on, so
+/// `Receiver` only handles the update while a host has the USB serial port
+/// open. Otherwise the update, e.g. one requested on `Serial1`, is handed
+/// to the system implementation (`System.firmwareUpdate`) on the port it
+/// was requested on.
+pub fn set_serial_handler() {
+    unsafe { ll::set_ymodem_serial_flash_update_handler(flash_update) }
+}
+
+/// Set while an update is handed to the system implementation
+static DELEGATED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn flash_update(
+    stream: *mut Stream,
+    file: &mut FileTransfer_Descriptor,
+    _: *mut ll::c_void,
+) -> bool {
+    // `Stream` is an opaque C++ object; we can only receive on USB
+    if UsbSerial.is_connected() {
+        return Receiver::new(UsbSerial).receive_into(file).is_ok();
+    }
+
+    // don't recurse if the system calls back into this handler
+    if DELEGATED.swap(true, Ordering::Acquire) {
+        return false;
+    }
+
+    let ok = unsafe { ll::system_firmwareUpdate(stream, ptr::null_mut()) };
+    DELEGATED.store(false, Ordering::Release);
+    ok
+}